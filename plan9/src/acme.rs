@@ -306,26 +306,61 @@ impl Event {
 	}
 }
 
+/// The unit columns are counted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+	/// Bytes.
+	Utf8,
+	/// UTF-16 code units.
+	Utf16,
+	/// Unicode scalar values, which are acme's runes.
+	Utf32,
+}
+
+impl Encoding {
+	/// Returns the number of units in s.
+	pub fn count(&self, s: &str) -> u32 {
+		s.chars().map(|c| self.char_len(c)).sum()
+	}
+	fn char_len(&self, c: char) -> u32 {
+		match self {
+			Encoding::Utf8 => c.len_utf8() as u32,
+			Encoding::Utf16 => c.len_utf16() as u32,
+			Encoding::Utf32 => 1,
+		}
+	}
+}
+
+/// Maps between acme rune offsets and line, column positions. Columns are counted in the
+/// encoding, which is runes unless set with with_encoding.
 #[derive(Debug)]
 pub struct NlOffsets {
 	nl: Vec<u32>,
 	leftover: u32,
+	lines: Vec<String>,
+	encoding: Encoding,
 }
 
 impl NlOffsets {
 	pub fn new<R: Read>(r: R) -> Result<NlOffsets> {
+		NlOffsets::with_encoding(r, Encoding::Utf32)
+	}
+	pub fn with_encoding<R: Read>(r: R, encoding: Encoding) -> Result<NlOffsets> {
 		let mut r = BufReader::new(r);
 		let mut nl = vec![0];
 		let mut o = 0;
 		let mut line = vec![];
 		let mut leftover = 0;
+		let mut lines = vec![];
 		loop {
 			line.clear();
 			let sz = r.read_until('\n' as u8, &mut line)?;
 			if sz == 0 {
 				break;
 			}
-			let n = std::str::from_utf8(&line)?.chars().count() as u32;
+			let s = std::str::from_utf8(&line)?;
+			let n = s.chars().count() as u32;
+			lines.push(s.to_string());
 			let last: u8 = *line.last().unwrap();
 			if last != '\n' as u8 {
 				leftover = n;
@@ -334,18 +369,26 @@ impl NlOffsets {
 			o += n;
 			nl.push(o);
 		}
-		Ok(NlOffsets { nl, leftover })
+		Ok(NlOffsets {
+			nl,
+			leftover,
+			lines,
+			encoding,
+		})
 	}
 	// returns line, col
 	pub fn offset_to_line(&self, offset: u32) -> (u32, u32) {
 		for (i, o) in self.nl.iter().enumerate() {
 			if *o > offset {
-				return (i as u32 - 1, offset - self.nl[i - 1]);
+				return (
+					i as u32 - 1,
+					self.runes_to_col(i - 1, offset - self.nl[i - 1]),
+				);
 			}
 		}
 		let i = self.nl.len() - 1;
 		if offset >= self.nl[i] {
-			return (i as u32, offset - self.nl[i]);
+			return (i as u32, self.runes_to_col(i, offset - self.nl[i]));
 		}
 		panic!("unreachable");
 	}
@@ -356,7 +399,7 @@ impl NlOffsets {
 			// beyond EOF, so just return the highest offset.
 			return eof;
 		}
-		let mut o = self.nl[line] + col;
+		let mut o = self.nl[line] + self.col_to_runes(line, col);
 		if o > eof {
 			o = eof;
 		}
@@ -367,7 +410,42 @@ impl NlOffsets {
 		if self.nl.is_empty() {
 			(0, self.leftover)
 		} else {
-			(self.nl.len() as u32 - 1, self.leftover)
+			let line = self.nl.len() - 1;
+			(line as u32, self.runes_to_col(line, self.leftover))
+		}
+	}
+	// converts a rune count from the start of line to a column in the encoding.
+	fn runes_to_col(&self, line: usize, runes: u32) -> u32 {
+		match (self.encoding, self.lines.get(line)) {
+			(Encoding::Utf32, _) | (_, None) => runes,
+			(_, Some(s)) => {
+				let n: u32 = s
+					.chars()
+					.take(runes as usize)
+					.map(|c| self.encoding.char_len(c))
+					.sum();
+				// Offsets past the end of the line keep counting.
+				n + runes.saturating_sub(s.chars().count() as u32)
+			}
+		}
+	}
+	// converts a column in the encoding to a rune count from the start of line. Columns in the
+	// middle of a character round up to the next one.
+	fn col_to_runes(&self, line: usize, col: u32) -> u32 {
+		match (self.encoding, self.lines.get(line)) {
+			(Encoding::Utf32, _) | (_, None) => col,
+			(_, Some(s)) => {
+				let mut units = 0;
+				let mut runes = 0;
+				for c in s.chars() {
+					if units >= col {
+						return runes;
+					}
+					units += self.encoding.char_len(c);
+					runes += 1;
+				}
+				runes + col.saturating_sub(units)
+			}
 		}
 	}
 }
@@ -392,6 +470,28 @@ mod tests {
 		assert_eq!(n.last(), (2, 2));
 	}
 
+	#[test]
+	fn nloffsets_encoding() {
+		// é is 2 UTF-8 bytes and 1 UTF-16 unit, 𝄞 is 4 bytes and 2 units.
+		let s = "aé𝄞b\nc𝄞";
+		let n = NlOffsets::with_encoding(s.as_bytes(), Encoding::Utf16).unwrap();
+		assert_eq!(n.offset_to_line(1), (0, 1));
+		assert_eq!(n.offset_to_line(3), (0, 4));
+		assert_eq!(n.offset_to_line(4), (0, 5));
+		assert_eq!(n.offset_to_line(6), (1, 1));
+		assert_eq!(n.offset_to_line(7), (1, 3));
+		assert_eq!(n.line_to_offset(0, 4), 3);
+		assert_eq!(n.line_to_offset(0, 5), 4);
+		assert_eq!(n.line_to_offset(1, 3), 7);
+		assert_eq!(n.last(), (1, 3));
+		let n = NlOffsets::with_encoding(s.as_bytes(), Encoding::Utf8).unwrap();
+		assert_eq!(n.offset_to_line(3), (0, 7));
+		assert_eq!(n.line_to_offset(0, 7), 3);
+		assert_eq!(n.line_to_offset(1, 5), 7);
+		assert_eq!(n.last(), (1, 5));
+		assert_eq!(Encoding::Utf16.count("aé𝄞"), 4);
+	}
+
 	#[test]
 	fn windows() {
 		let ws = WinInfo::windows().unwrap();
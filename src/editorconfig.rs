@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use regex::Regex;
+
+/// Returns the EditorConfig properties for path. The .editorconfig files of the file's
+/// directory and its parents are read until one has root = true, with closer files and later
+/// sections overriding earlier ones. Keys and values are lowercased.
+pub fn properties(path: &Path) -> HashMap<String, String> {
+	let mut files = vec![];
+	let mut dir = path.parent();
+	while let Some(d) = dir {
+		if let Ok(s) = read_to_string(d.join(".editorconfig")) {
+			let root = is_root(&s);
+			files.push((d.to_path_buf(), s));
+			if root {
+				break;
+			}
+		}
+		dir = d.parent();
+	}
+	let mut props = HashMap::new();
+	for (dir, s) in files.iter().rev() {
+		let rel = match path.strip_prefix(dir) {
+			Ok(rel) => rel.to_string_lossy().to_string(),
+			Err(_) => continue,
+		};
+		apply(s, &rel, &mut props);
+	}
+	props
+}
+
+/// Reports whether the preamble of an .editorconfig file sets root = true.
+fn is_root(s: &str) -> bool {
+	for line in s.lines() {
+		let line = line.trim();
+		if line.starts_with('[') {
+			break;
+		}
+		if let Some((k, v)) = line.split_once('=') {
+			if k.trim().eq_ignore_ascii_case("root") && v.trim().eq_ignore_ascii_case("true") {
+				return true;
+			}
+		}
+	}
+	false
+}
+
+/// Sets the properties of the sections of an .editorconfig file that match rel, the path of
+/// the file relative to the .editorconfig's directory.
+fn apply(s: &str, rel: &str, props: &mut HashMap<String, String>) {
+	let mut matched = false;
+	for line in s.lines() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+			continue;
+		}
+		if line.starts_with('[') && line.ends_with(']') {
+			let glob = &line[1..line.len() - 1];
+			matched = match Regex::new(&glob_regex(glob)) {
+				Ok(re) => re.is_match(rel),
+				Err(_) => false,
+			};
+			continue;
+		}
+		if !matched {
+			continue;
+		}
+		if let Some((k, v)) = line.split_once('=') {
+			props.insert(k.trim().to_lowercase(), v.trim().to_lowercase());
+		}
+	}
+}
+
+/// Converts an EditorConfig glob to an anchored regex. Globs without a slash match the file
+/// name in any directory. Numeric ranges ({1..3}) match any number.
+fn glob_regex(glob: &str) -> String {
+	let mut re = String::from("^");
+	if !glob.contains('/') {
+		re.push_str("(?:.*/)?");
+	}
+	let glob = glob.strip_prefix('/').unwrap_or(glob);
+	let chars: Vec<char> = glob.chars().collect();
+	let mut braces = 0;
+	let mut i = 0;
+	while i < chars.len() {
+		let c = chars[i];
+		match c {
+			'*' if chars.get(i + 1) == Some(&'*') => {
+				re.push_str(".*");
+				i += 1;
+			}
+			'*' => re.push_str("[^/]*"),
+			'?' => re.push_str("[^/]"),
+			'[' => match chars[i..].iter().position(|&c| c == ']') {
+				Some(end) => {
+					let class: String = chars[i + 1..i + end].iter().collect();
+					match class.strip_prefix('!') {
+						Some(class) => re.push_str(&format!("[^{}]", class)),
+						None => re.push_str(&format!("[{}]", class)),
+					}
+					i += end;
+				}
+				None => re.push_str("\\["),
+			},
+			'{' => {
+				let end = chars[i..].iter().position(|&c| c == '}');
+				let inner: Option<String> = end.map(|end| chars[i + 1..i + end].iter().collect());
+				match inner {
+					Some(inner) if is_num_range(&inner) => {
+						re.push_str("[+-]?[0-9]+");
+						i += end.unwrap();
+					}
+					_ => {
+						re.push_str("(?:");
+						braces += 1;
+					}
+				}
+			}
+			'}' if braces > 0 => {
+				re.push(')');
+				braces -= 1;
+			}
+			',' if braces > 0 => re.push('|'),
+			'\\' if i + 1 < chars.len() => {
+				re.push_str(&regex::escape(&chars[i + 1].to_string()));
+				i += 1;
+			}
+			c => re.push_str(&regex::escape(&c.to_string())),
+		}
+		i += 1;
+	}
+	re.push('$');
+	re
+}
+
+fn is_num_range(s: &str) -> bool {
+	match s.split_once("..") {
+		Some((a, b)) => a.parse::<i64>().is_ok() && b.parse::<i64>().is_ok(),
+		None => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn matches(glob: &str, path: &str) -> bool {
+		Regex::new(&glob_regex(glob)).unwrap().is_match(path)
+	}
+
+	#[test]
+	fn globs() {
+		assert!(matches("*", "a.rs"));
+		assert!(matches("*", "src/a.rs"));
+		assert!(matches("*.py", "src/a.py"));
+		assert!(!matches("*.py", "src/a.rs"));
+		assert!(matches("*.{yml,yaml}", "ci/a.yaml"));
+		assert!(!matches("*.{yml,yaml}", "a.toml"));
+		assert!(matches("src/*.rs", "src/a.rs"));
+		assert!(!matches("src/*.rs", "src/b/a.rs"));
+		assert!(matches("/src/**.rs", "src/b/a.rs"));
+		assert!(matches("Makefile", "sub/Makefile"));
+		assert!(matches("file{1..3}.txt", "file2.txt"));
+		assert!(matches("[ab].c", "b.c"));
+		assert!(!matches("[!ab].c", "b.c"));
+	}
+
+	#[test]
+	fn sections() {
+		let s = "root = true\n\n[*]\nindent_style = tab\n\n# yaml\n[*.yaml]\nindent_style = Space\nindent_size = 2\n";
+		assert!(is_root(s));
+		let mut props = HashMap::new();
+		apply(s, "a/b.yaml", &mut props);
+		assert_eq!(props.get("indent_style").unwrap(), "space");
+		assert_eq!(props.get("indent_size").unwrap(), "2");
+		let mut props = HashMap::new();
+		apply(s, "a/b.rs", &mut props);
+		assert_eq!(props.get("indent_style").unwrap(), "tab");
+		assert_eq!(props.get("indent_size"), None);
+	}
+}
@@ -0,0 +1,63 @@
+/// Scores how well text matches pattern, or returns None if the characters of pattern do not all
+/// appear in order in text. Higher is better. Matching is case insensitive unless pattern has an
+/// uppercase letter. Consecutive matches and matches at the start of words (after _ or -, or a
+/// lowercase to uppercase change) score more, and skipped characters cost a little.
+pub fn score(pattern: &str, text: &str) -> Option<i32> {
+	let smart_case = pattern.chars().any(|c| c.is_uppercase());
+	let eq = |a: char, b: char| {
+		if smart_case {
+			a == b
+		} else {
+			a.to_lowercase().eq(b.to_lowercase())
+		}
+	};
+	let text: Vec<char> = text.chars().collect();
+	let mut score = 0;
+	let mut pos = 0;
+	let mut prev_match: Option<usize> = None;
+	for p in pattern.chars() {
+		let idx = pos + text[pos..].iter().position(|&c| eq(p, c))?;
+		let boundary = idx == 0
+			|| matches!(text[idx - 1], '_' | '-' | '.' | ':')
+			|| (text[idx - 1].is_lowercase() && text[idx].is_uppercase());
+		score += 1;
+		if boundary {
+			score += 8;
+		}
+		match prev_match {
+			Some(prev) if prev + 1 == idx => score += 5,
+			Some(prev) => score -= (idx - prev - 1).min(5) as i32,
+			// The first match costs the characters skipped to reach it.
+			None => score -= idx.min(5) as i32,
+		}
+		if text[idx] == p {
+			score += 1;
+		}
+		prev_match = Some(idx);
+		pos = idx + 1;
+	}
+	Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn scores() {
+		assert_eq!(score("xyz", "abc"), None);
+		assert_eq!(score("ba", "ab"), None);
+		assert!(score("", "abc").is_some());
+		assert!(score("abc", "aXbXc").is_some());
+		// Prefixes beat substrings, which beat scattered matches.
+		assert!(score("set", "set_hover") > score("set", "reset"));
+		assert!(score("set", "reset") > score("set", "sxexxt"));
+		// Word starts count.
+		assert!(score("sh", "set_hover") > score("sh", "push"));
+		assert!(score("sh", "setHover") > score("sh", "push"));
+		// Smart case.
+		assert!(score("Hover", "hover").is_none());
+		assert!(score("hover", "Hover").is_some());
+		assert!(score("hover", "hover") > score("hover", "Hover"));
+	}
+}
@@ -110,7 +110,97 @@ impl Client {
 			root_uri,
 			initialization_options: options,
 			capabilities: ClientCapabilities {
+				general: Some(GeneralClientCapabilities {
+					// acme addresses runes, so prefer the encodings cheapest to convert.
+					position_encodings: Some(vec![
+						PositionEncodingKind::UTF32,
+						PositionEncodingKind::UTF8,
+						PositionEncodingKind::UTF16,
+					]),
+					..Default::default()
+				}),
+				workspace: Some(WorkspaceClientCapabilities {
+					apply_edit: Some(true),
+					configuration: Some(true),
+					workspace_edit: Some(WorkspaceEditClientCapabilities {
+						document_changes: Some(true),
+						resource_operations: Some(vec![
+							ResourceOperationKind::Create,
+							ResourceOperationKind::Rename,
+							ResourceOperationKind::Delete,
+						]),
+						change_annotation_support: Some(
+							ChangeAnnotationWorkspaceEditClientCapabilities {
+								groups_on_label: Some(false),
+							},
+						),
+						..Default::default()
+					}),
+					execute_command: Some(DynamicRegistrationClientCapabilities {
+						dynamic_registration: Some(false),
+					}),
+					symbol: Some(WorkspaceSymbolClientCapabilities {
+						resolve_support: Some(WorkspaceSymbolResolveSupportCapability {
+							properties: vec!["location.range".to_string()],
+						}),
+						..Default::default()
+					}),
+					code_lens: Some(CodeLensWorkspaceClientCapabilities {
+						refresh_support: Some(true),
+					}),
+					diagnostic: Some(DiagnosticWorkspaceClientCapabilities {
+						refresh_support: Some(true),
+					}),
+					..Default::default()
+				}),
+				window: Some(WindowClientCapabilities {
+					work_done_progress: Some(true),
+					show_message: Some(ShowMessageRequestClientCapabilities {
+						message_action_item: None,
+					}),
+					..Default::default()
+				}),
 				text_document: Some(TextDocumentClientCapabilities {
+					call_hierarchy: Some(CallHierarchyClientCapabilities {
+						dynamic_registration: Some(false),
+					}),
+					type_hierarchy: Some(TypeHierarchyClientCapabilities {
+						dynamic_registration: Some(false),
+					}),
+					code_lens: Some(CodeLensClientCapabilities {
+						dynamic_registration: Some(false),
+					}),
+					completion: Some(CompletionClientCapabilities {
+						completion_item: Some(CompletionItemCapability {
+							snippet_support: Some(true),
+							insert_replace_support: Some(true),
+							resolve_support: Some(CompletionItemCapabilityResolveSupport {
+								properties: vec![
+									"additionalTextEdits".to_string(),
+									"detail".to_string(),
+									"documentation".to_string(),
+								],
+							}),
+							..Default::default()
+						}),
+						..Default::default()
+					}),
+					folding_range: Some(FoldingRangeClientCapabilities {
+						line_folding_only: Some(true),
+						..Default::default()
+					}),
+					inlay_hint: Some(InlayHintClientCapabilities {
+						dynamic_registration: Some(false),
+						resolve_support: None,
+					}),
+					publish_diagnostics: Some(PublishDiagnosticsClientCapabilities {
+						related_information: Some(true),
+						..Default::default()
+					}),
+					diagnostic: Some(DiagnosticClientCapabilities {
+						related_document_support: Some(true),
+						..Default::default()
+					}),
 					code_action: Some(CodeActionClientCapabilities {
 						resolve_support: Some(CodeActionCapabilityResolveSupport {
 							properties: vec!["edit".to_string()],
@@ -133,7 +223,16 @@ impl Client {
 					}),
 					..Default::default()
 				}),
-				..Default::default()
+				// rust-analyzer only returns lenses for the client commands listed here.
+				experimental: Some(serde_json::json!({
+					"commands": {
+						"commands": [
+							"rust-analyzer.runSingle",
+							"rust-analyzer.debugSingle",
+							"rust-analyzer.showReferences",
+						],
+					},
+				})),
 			},
 			trace: None,
 			workspace_folders,
@@ -166,6 +265,34 @@ impl Client {
 		write!(self.stdin, "{}", s)?;
 		Ok(())
 	}
+	/// Replies to a request the server sent to us.
+	pub fn respond<R: Request>(&mut self, id: NumberOrString, result: R::Result) -> Result<()> {
+		let msg = ResponseMessage {
+			jsonrpc: "2.0",
+			id,
+			result,
+		};
+		let s = serde_json::to_string(&msg)?;
+		let s = format!("Content-Length: {}\r\n\r\n{}", s.len(), s);
+		write!(self.stdin, "{}", s)?;
+		Ok(())
+	}
+	/// Replies to a request the server sent to us with an error.
+	pub fn respond_error(&mut self, id: NumberOrString, code: i64, message: String) -> Result<()> {
+		let msg = ErrorMessage {
+			jsonrpc: "2.0",
+			id,
+			error: ResponseError {
+				code,
+				message,
+				data: None,
+			},
+		};
+		let s = serde_json::to_string(&msg)?;
+		let s = format!("Content-Length: {}\r\n\r\n{}", s.len(), s);
+		write!(self.stdin, "{}", s)?;
+		Ok(())
+	}
 	fn new_id(&mut self) -> Result<usize> {
 		let id = self.next_id;
 		self.next_id += 1;
@@ -194,18 +321,77 @@ struct NotificationMessage<P> {
 	params: P,
 }
 
+#[derive(serde::Serialize)]
+struct ResponseMessage<R> {
+	jsonrpc: &'static str,
+	id: NumberOrString,
+	result: R,
+}
+
+#[derive(serde::Serialize)]
+struct ErrorMessage {
+	jsonrpc: &'static str,
+	id: NumberOrString,
+	error: ResponseError,
+}
+
+/// JSON-RPC error code for requests with an unknown method.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
 #[derive(Debug, serde::Deserialize)]
 pub struct DeMessage {
-	pub id: Option<usize>,
+	/// A number for responses to our requests, but server requests may use strings.
+	pub id: Option<NumberOrString>,
 	pub method: Option<String>,
 	pub params: Option<Box<serde_json::value::RawValue>>,
 	pub result: Option<Box<serde_json::value::RawValue>>,
 	pub error: Option<ResponseError>,
 }
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct ResponseError {
 	pub code: i64,
 	pub message: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
 	pub data: Option<serde_json::Value>,
 }
+
+/// rust-analyzer's experimental/runnables request, which lists the tests and binaries that can
+/// be run at a position.
+pub enum Runnables {}
+
+impl Request for Runnables {
+	type Params = RunnablesParams;
+	type Result = Vec<Runnable>;
+	const METHOD: &'static str = "experimental/runnables";
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunnablesParams {
+	pub text_document: TextDocumentIdentifier,
+	pub position: Option<Position>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Runnable {
+	pub label: String,
+	pub location: Option<LocationLink>,
+	pub kind: String,
+	pub args: RunnableArgs,
+}
+
+/// Arguments of a cargo runnable. Older rust-analyzers send cargo_extra_args, newer ones cwd
+/// and environment.
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RunnableArgs {
+	pub workspace_root: Option<String>,
+	pub cwd: Option<String>,
+	pub override_cargo: Option<String>,
+	pub environment: HashMap<String, String>,
+	pub cargo_args: Vec<String>,
+	pub cargo_extra_args: Vec<String>,
+	pub executable_args: Vec<String>,
+}
@@ -5,24 +5,29 @@ use std::fmt::Write;
 use std::fs::{metadata, read_to_string};
 use std::io::Read;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Error, Result};
 use crossbeam_channel::{bounded, Receiver, Select};
 use diff;
-use lazy_static::lazy_static;
 use lsp_types::{notification::*, request::*, *};
 use nine::p2000::OpenMode;
-use regex::Regex;
 use serde::Deserialize;
 use serde_json::Value;
 
 use plan9::{acme::*, plumb};
 
+mod editorconfig;
+mod fuzzy;
 mod lsp;
+mod snippet;
 
 #[derive(Deserialize)]
 struct TomlConfig {
 	servers: HashMap<String, ConfigServer>,
+	plumb_edited_files: Option<bool>,
+	max_completions: Option<usize>,
+	max_actions: Option<usize>,
 }
 
 #[derive(Clone, Deserialize)]
@@ -35,9 +40,100 @@ struct ConfigServer {
 	options: Option<Value>,
 	actions_on_put: Option<Vec<CodeActionKind>>,
 	format_on_put: Option<bool>,
+	format_on_type: Option<bool>,
+	formatting: Option<ConfigFormatting>,
+	completion_edit: Option<CompletionEdit>,
 	env: Option<HashMap<String, String>>,
 }
 
+/// Which range of an insert and replace completion edit to use.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CompletionEdit {
+	/// Insert before the cursor, keeping the rest of the identifier.
+	Insert,
+	/// Replace the whole identifier under the cursor.
+	Replace,
+}
+
+/// FormattingOptions, any of which may be left unset to keep the default.
+#[derive(Clone, Default, Deserialize)]
+struct ConfigFormatting {
+	tab_size: Option<u32>,
+	insert_spaces: Option<bool>,
+	trim_trailing_whitespace: Option<bool>,
+	insert_final_newline: Option<bool>,
+	trim_final_newlines: Option<bool>,
+}
+
+impl ConfigFormatting {
+	/// Overrides the options that are set.
+	fn apply(&self, opts: &mut FormattingOptions) {
+		if let Some(v) = self.tab_size {
+			opts.tab_size = v;
+		}
+		if let Some(v) = self.insert_spaces {
+			opts.insert_spaces = v;
+		}
+		if let Some(v) = self.trim_trailing_whitespace {
+			opts.trim_trailing_whitespace = Some(v);
+		}
+		if let Some(v) = self.insert_final_newline {
+			opts.insert_final_newline = Some(v);
+		}
+		if let Some(v) = self.trim_final_newlines {
+			opts.trim_final_newlines = Some(v);
+		}
+	}
+	/// Returns the options set by EditorConfig properties.
+	fn from_editorconfig(props: &HashMap<String, String>) -> ConfigFormatting {
+		let bool_prop = |k: &str| match props.get(k).map(|v| v.as_str()) {
+			Some("true") => Some(true),
+			Some("false") => Some(false),
+			_ => None,
+		};
+		let indent_size = match props.get("indent_size").map(|v| v.as_str()) {
+			Some("tab") | None => props.get("tab_width"),
+			Some(_) => props.get("indent_size"),
+		};
+		ConfigFormatting {
+			tab_size: indent_size.and_then(|v| v.parse().ok()),
+			insert_spaces: match props.get("indent_style").map(|v| v.as_str()) {
+				Some("space") => Some(true),
+				Some("tab") => Some(false),
+				_ => None,
+			},
+			trim_trailing_whitespace: bool_prop("trim_trailing_whitespace"),
+			insert_final_newline: bool_prop("insert_final_newline"),
+			trim_final_newlines: None,
+		}
+	}
+}
+
+/// A project-local .acre.toml, found in the directory of a file or one of its parents.
+#[derive(Deserialize)]
+struct ProjectConfig {
+	formatting: Option<ConfigFormatting>,
+}
+
+/// Returns the formatting table of the .acre.toml closest to path.
+fn project_formatting(path: &std::path::Path) -> Option<ConfigFormatting> {
+	let mut dir = path.parent();
+	while let Some(d) = dir {
+		if let Ok(s) = read_to_string(d.join(".acre.toml")) {
+			return match toml::from_str::<ProjectConfig>(&s) {
+				Ok(config) => config.formatting,
+				Err(err) => {
+					eprintln!("{}: {}", d.join(".acre.toml").display(), err);
+					None
+				}
+			};
+		}
+		dir = d.parent();
+	}
+	None
+}
+
 fn main() -> Result<()> {
 	let dir = xdg::BaseDirectories::new()?;
 	const ACRE_TOML: &str = "acre.toml";
@@ -138,7 +234,15 @@ struct Server {
 	focus: String,
 	progress: HashMap<String, WDProgress>,
 	/// file name -> list of diagnostics
-	diags: BTreeMap<String, Vec<String>>,
+	diags: BTreeMap<String, Vec<Diagnostic>>,
+	/// file name -> result id of the last pulled diagnostics report
+	diag_result_ids: HashMap<String, String>,
+	/// window listing every diagnostic, if open
+	diag_w: Option<Win>,
+	/// cached body of diag_w
+	diag_body: String,
+	/// file name -> cached diag_w lines of the file
+	diag_sections: HashMap<String, DiagSection>,
 	/// request (client_name, id) -> (method, file Url)
 	requests: HashMap<ClientId, (String, Url)>,
 
@@ -157,6 +261,179 @@ struct Server {
 	files: HashMap<String, String>,
 	/// list of LSP message IDs to auto-run actions
 	autorun: HashMap<usize, ()>,
+	/// prepareRename request -> rename to send if the prepare succeeds
+	renames: HashMap<ClientId, RenameParams>,
+	/// results of the last workspace/symbol request
+	workspace_symbols: Vec<WorkspaceSymbol>,
+	/// client of workspace_symbols
+	symbol_client: String,
+	/// output listing workspace_symbols, and (output line, symbol index) of the symbols that
+	/// are resolved when clicked
+	symbol_lines: (String, Vec<(usize, usize)>),
+	/// Vec of (start, end, symbol index) to map Look locations to symbols to resolve.
+	symbol_addrs: Vec<(usize, usize, usize)>,
+	/// workspaceSymbol/resolve request -> index into workspace_symbols
+	symbol_resolves: HashMap<ClientId, usize>,
+	/// codeLens/resolve request -> index into the current hover's lenses
+	lens_resolves: HashMap<ClientId, usize>,
+	/// edits to files sent to the plumber, applied once their windows open or written to
+	/// disk if they haven't opened after PLUMB_TIMEOUT
+	plumbed_edits: HashMap<Url, PlumbedEdits>,
+	/// workspace/applyEdit requests to answer once their plumbed_edits are applied
+	edit_replies: Vec<EditReply>,
+	/// window/showMessageRequests waiting for the user to pick an action.
+	message_requests: Vec<MessageRequest>,
+	/// Vec of (start, end, message request index, action index) to map Look locations
+	/// to message actions.
+	message_addrs: Vec<(usize, usize, usize, usize)>,
+	/// workspace edits waiting for the user to confirm their change annotations
+	pending_edits: Vec<PendingEdit>,
+	/// Vec of (start, end, pending edit index, annotation id, accept) to map Look locations
+	/// to confirmations.
+	pending_edit_addrs: Vec<(usize, usize, usize, String, bool)>,
+	/// call or type hierarchy shown below the output, if any
+	hierarchy: Option<Hierarchy>,
+	/// Vec of (start, end, node index) to map Look locations to hierarchy expanders.
+	hierarchy_addrs: Vec<(usize, usize, usize)>,
+	/// calls or types request -> hierarchy node being expanded
+	hierarchy_expands: HashMap<ClientId, usize>,
+	/// selectionRange request -> (whether to expand, range of dot when requested)
+	selection_ranges: HashMap<ClientId, (bool, Range)>,
+	/// foldingRange request -> dot to select the enclosing region of, or None to list an outline
+	folding_ranges: HashMap<ClientId, Option<Range>>,
+	/// last inserted snippet with tabstops left to visit
+	snippet: Option<Snippet>,
+	/// (client name, runnable) listed below the output
+	runnables: Vec<(String, lsp::Runnable)>,
+	/// Vec of (start, end, runnable index) to map Look locations to runnables.
+	runnable_addrs: Vec<(usize, usize, usize)>,
+	/// client names whose servers advertise typeHierarchyProvider, which
+	/// ServerCapabilities doesn't have a field for
+	type_hierarchy: HashSet<String>,
+}
+
+/// The tabstops of an inserted snippet.
+struct Snippet {
+	url: Url,
+	/// rune ranges of the tabstops in visiting order
+	tabstops: Vec<(u32, u32)>,
+	/// index of the next tabstop to select
+	next: usize,
+	/// rune length of the body when the tabstops were last moved, to shift them by what was
+	/// typed since
+	len: u32,
+}
+
+/// The diagnostics window lines of a file. They are reused until the file's diagnostics or
+/// text change, so that files not open in acme aren't reread on every sync.
+struct DiagSection {
+	stamp: TextStamp,
+	diags: Vec<Diagnostic>,
+	text: String,
+}
+
+/// Identifies the version of a file's text that diagnostic columns were converted with.
+#[derive(PartialEq)]
+enum TextStamp {
+	/// document version of an open window
+	Window(i32),
+	/// modification time of a file on disk
+	File(Option<std::time::SystemTime>),
+}
+
+/// A window/showMessageRequest from a server.
+struct MessageRequest {
+	client_name: String,
+	id: NumberOrString,
+	params: ShowMessageRequestParams,
+}
+
+/// A workspace edit with change annotations that need confirmation.
+struct PendingEdit {
+	label: Option<String>,
+	edit: WorkspaceEdit,
+	/// client name and id of the workspace/applyEdit request to answer once applied
+	request: Option<(String, NumberOrString)>,
+	/// annotation id -> whether it was accepted, once decided
+	confirmations: BTreeMap<String, Option<bool>>,
+}
+
+/// A workspace/applyEdit request whose reply waits for edits to plumbed files to land.
+struct EditReply {
+	client_name: String,
+	id: NumberOrString,
+	label: Option<String>,
+	/// files whose plumbed edits haven't been applied yet
+	waiting: HashSet<Url>,
+	/// the first error applying the plumbed edits
+	err: Option<String>,
+}
+
+/// Edits to a file sent to the plumber, waiting for its window to open.
+struct PlumbedEdits {
+	/// when the file was sent to the plumber
+	since: Instant,
+	edits: Vec<(InsertTextFormat, Vec<TextEdit>)>,
+}
+
+/// A call or type hierarchy, flattened in display order.
+struct Hierarchy {
+	client_name: String,
+	kind: HierarchyKind,
+	nodes: Vec<HierarchyNode>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum HierarchyKind {
+	/// Incoming calls.
+	Callers,
+	/// Outgoing calls.
+	Callees,
+	Supertypes,
+	Subtypes,
+}
+
+impl HierarchyKind {
+	fn name(&self) -> &'static str {
+		match self {
+			HierarchyKind::Callers => "callers",
+			HierarchyKind::Callees => "callees",
+			HierarchyKind::Supertypes => "supertypes",
+			HierarchyKind::Subtypes => "subtypes",
+		}
+	}
+}
+
+struct HierarchyNode {
+	item: HierarchyItem,
+	depth: usize,
+	expanded: bool,
+}
+
+enum HierarchyItem {
+	Call(CallHierarchyItem),
+	Type(TypeHierarchyItem),
+}
+
+impl HierarchyItem {
+	fn name(&self) -> &str {
+		match self {
+			HierarchyItem::Call(item) => &item.name,
+			HierarchyItem::Type(item) => &item.name,
+		}
+	}
+	fn kind(&self) -> SymbolKind {
+		match self {
+			HierarchyItem::Call(item) => item.kind,
+			HierarchyItem::Type(item) => item.kind,
+		}
+	}
+	fn location(&self) -> Location {
+		match self {
+			HierarchyItem::Call(item) => Location::new(item.uri.clone(), item.selection_range),
+			HierarchyItem::Type(item) => Location::new(item.uri.clone(), item.selection_range),
+		}
+	}
 }
 
 #[derive(Debug)]
@@ -165,16 +442,31 @@ struct WindowHover {
 	url: Url,
 	/// line text of the hover.
 	line: String,
+	/// cursor range of the hover.
+	range: Range,
+	/// position encoding of the server.
+	encoding: Encoding,
 	/// token (word at the cursor) of the hover.
 	token: Option<String>,
 	/// on hover response from lsp
 	hover: Option<String>,
 	/// result of signature request
 	signature: Option<String>,
+	/// code lenses of the cursor line
 	lens: Vec<CodeLens>,
+	/// line number of the first line in hint_lines
+	first_hint_line: u32,
+	/// text of the lines around the cursor that inlay hints are requested for
+	hint_lines: Vec<String>,
+	/// inlay hints of hint_lines
+	hints: Vec<InlayHint>,
+	/// occurrences of the symbol at the cursor, sorted by position
+	highlights: Vec<DocumentHighlight>,
 	/// completion response. we need to cache this because we also need the token
 	/// response to come, and we don't know which will come first.
 	completion: Vec<CompletionItem>,
+	/// whether the server said completion should be requested again as the word is typed
+	completion_incomplete: bool,
 	code_actions: Vec<Action>,
 
 	/// merged actions from the code action and completion requests
@@ -191,10 +483,14 @@ struct ServerWin {
 	url: Url,
 	version: i32,
 	client: String,
+	/// Position encoding negotiated with the client.
+	encoding: Encoding,
+	/// The body as last sent to the server, used to compute incremental changes.
+	synced: String,
 }
 
 impl ServerWin {
-	fn new(name: String, w: Win, client: String) -> Result<ServerWin> {
+	fn new(name: String, w: Win, client: String, encoding: Encoding) -> Result<ServerWin> {
 		let url = Url::parse(&format!("file://{}", name))?;
 		let version = 1;
 		Ok(ServerWin {
@@ -202,15 +498,17 @@ impl ServerWin {
 			url,
 			version,
 			client,
+			encoding,
+			synced: String::new(),
 		})
 	}
 	fn pos(&mut self) -> Result<(u32, u32)> {
 		self.w.ctl("addr=dot")?;
-		// TODO: convert these character (rune) offsets to byte offsets.
 		self.w.read_addr()
 	}
 	fn nl(&mut self) -> Result<NlOffsets> {
-		NlOffsets::new(self.w.read(File::Body)?)
+		let encoding = self.encoding;
+		NlOffsets::with_encoding(self.w.read(File::Body)?, encoding)
 	}
 	fn range(&mut self) -> Result<Range> {
 		let pos = self.pos()?;
@@ -227,16 +525,39 @@ impl ServerWin {
 		self.version += 1;
 		Ok((self.version, buf))
 	}
-	fn change_params(&mut self) -> Result<DidChangeTextDocumentParams> {
+	/// Returns the changes since the text was last synced, or None if there were none.
+	fn change_params(
+		&mut self,
+		kind: TextDocumentSyncKind,
+	) -> Result<Option<DidChangeTextDocumentParams>> {
 		let (version, text) = self.text()?;
-		Ok(DidChangeTextDocumentParams {
-			text_document: VersionedTextDocumentIdentifier::new(self.url.clone(), version),
-			content_changes: vec![TextDocumentContentChangeEvent {
+		if text == self.synced {
+			return Ok(None);
+		}
+		let content_changes = if kind == TextDocumentSyncKind::INCREMENTAL {
+			text_changes(&self.synced, &text, self.encoding)
+		} else {
+			vec![TextDocumentContentChangeEvent {
 				range: None,
 				range_length: None,
-				text,
-			}],
-		})
+				text: text.clone(),
+			}]
+		};
+		self.synced = text;
+		Ok(Some(DidChangeTextDocumentParams {
+			text_document: VersionedTextDocumentIdentifier::new(self.url.clone(), version),
+			content_changes,
+		}))
+	}
+	/// Sets dot to range and shows it.
+	fn select(&mut self, range: &Range) -> Result<()> {
+		let nl = self.nl()?;
+		let start = nl.line_to_offset(range.start.line, range.start.character);
+		let end = nl.line_to_offset(range.end.line, range.end.character);
+		self.w.addr(&format!("#{},#{}", start, end))?;
+		self.w.ctl("dot=addr")?;
+		self.w.ctl("show")?;
+		Ok(())
 	}
 	fn doc_ident(&self) -> TextDocumentIdentifier {
 		TextDocumentIdentifier::new(self.url.clone())
@@ -309,6 +630,10 @@ impl Server {
 			progress: HashMap::new(),
 			requests,
 			diags: BTreeMap::new(),
+			diag_result_ids: HashMap::new(),
+			diag_w: None,
+			diag_body: "".to_string(),
+			diag_sections: HashMap::new(),
 			current_hover: None,
 			log_r,
 			ev_r,
@@ -318,6 +643,28 @@ impl Server {
 			files: HashMap::new(),
 			config,
 			autorun: HashMap::new(),
+			renames: HashMap::new(),
+			workspace_symbols: vec![],
+			symbol_client: "".to_string(),
+			symbol_lines: ("".to_string(), vec![]),
+			symbol_addrs: vec![],
+			symbol_resolves: HashMap::new(),
+			lens_resolves: HashMap::new(),
+			plumbed_edits: HashMap::new(),
+			edit_replies: vec![],
+			message_requests: vec![],
+			message_addrs: vec![],
+			pending_edits: vec![],
+			pending_edit_addrs: vec![],
+			hierarchy: None,
+			hierarchy_addrs: vec![],
+			hierarchy_expands: HashMap::new(),
+			type_hierarchy: HashSet::new(),
+			selection_ranges: HashMap::new(),
+			folding_ranges: HashMap::new(),
+			snippet: None,
+			runnables: vec![],
+			runnable_addrs: vec![],
 		};
 		let err_s1 = err_s.clone();
 		thread::Builder::new()
@@ -355,11 +702,13 @@ impl Server {
 					}
 				};
 				match ev.c2 {
-					'x' | 'X' => match ev.text.as_str() {
+					'x' | 'X' => match ev.text.trim_matches(|c| c == '[' || c == ']') {
 						"Del" => {
 							return;
 						}
-						"Get" => {
+						// rename and wsymbols are executed so that they can be chorded with
+						// the new name or query.
+						"Get" | "Diagnostics" | "rename" | "wsymbols" => {
 							ev_s.send(ev).unwrap();
 						}
 						_ => {
@@ -379,6 +728,8 @@ impl Server {
 	/// Runs f if self.current_hover is Some and matches the Url, and updates the hover action addrs
 	/// and body.
 	fn set_hover<F: FnOnce(&mut WindowHover)>(&mut self, url: &Url, f: F) {
+		let max_completions = self.config.max_completions.unwrap_or(10);
+		let max_actions = self.config.max_actions.unwrap_or(10);
 		if let Some(hover) = self.current_hover.as_mut() {
 			if &hover.url == url {
 				f(hover);
@@ -386,33 +737,45 @@ impl Server {
 				hover.actions.clear();
 				hover.actions.extend(hover.code_actions.clone());
 				if let Some(token) = &hover.token {
-					let mut v = vec![];
-					for a in &hover.completion {
-						let filter = if let Some(ref filter) = a.filter_text {
-							filter.clone()
-						} else {
-							a.label.clone()
-						};
-						if filter.contains(token) {
-							v.push(Action::Completion(a.clone()));
-							if v.len() == 10 {
-								break;
-							}
-						}
-					}
-					hover.actions.extend(v);
+					let mut v: Vec<(i32, &CompletionItem)> = hover
+						.completion
+						.iter()
+						.filter_map(|a| {
+							let filter = a.filter_text.as_ref().unwrap_or(&a.label);
+							fuzzy::score(token, filter).map(|score| (score, a))
+						})
+						.collect();
+					// Preselected items first, then the best matches, with the server's order
+					// breaking ties.
+					v.sort_by(|(score_a, a), (score_b, b)| {
+						let sort_a = a.sort_text.as_ref().unwrap_or(&a.label);
+						let sort_b = b.sort_text.as_ref().unwrap_or(&b.label);
+						b.preselect
+							.unwrap_or(false)
+							.cmp(&a.preselect.unwrap_or(false))
+							.then(score_b.cmp(score_a))
+							.then(sort_a.cmp(sort_b))
+					});
+					hover.actions.extend(
+						v.into_iter()
+							.take(max_completions)
+							.map(|(_, a)| Action::Completion(a.clone())),
+					);
 				}
 
-				// Until lenses work, forcibly clear them.
-				hover.lens.clear();
-				hover
-					.actions
-					.extend(hover.lens.iter().map(|lens| Action::CodeLens(lens.clone())));
+				// Lenses still being resolved have nothing to run yet.
+				hover.actions.extend(
+					hover
+						.lens
+						.iter()
+						.filter(|lens| lens.command.is_some())
+						.map(|lens| Action::CodeLens(lens.clone())),
+				);
 
 				hover.body.clear();
 
 				hover.action_addrs.clear();
-				for (idx, action) in hover.actions.iter().take(10).enumerate() {
+				for (idx, action) in hover.actions.iter().take(max_actions).enumerate() {
 					hover.action_addrs.push((hover.body.len(), Some(idx)));
 					let newline = if hover.body.is_empty() { "" } else { "\n" };
 					match action {
@@ -434,8 +797,6 @@ impl Server {
 								write!(&mut hover.body, " {}", d).unwrap();
 							}
 						}
-						// TODO: extract out the range text and append it to the command title to
-						// distinguish between lenses.
 						Action::CodeLens(lens) => {
 							write!(
 								&mut hover.body,
@@ -447,6 +808,31 @@ impl Server {
 									.unwrap_or("unknown command".into())
 							)
 							.unwrap();
+							// Append the range text to distinguish between lenses.
+							if let Ok(nl) =
+								NlOffsets::with_encoding(hover.line.as_bytes(), hover.encoding)
+							{
+								let line = hover.range.start.line;
+								let start = if lens.range.start.line == line {
+									nl.line_to_offset(0, lens.range.start.character)
+								} else {
+									0
+								};
+								let end = if lens.range.end.line == line {
+									nl.line_to_offset(0, lens.range.end.character)
+								} else {
+									hover.line.chars().count() as u32
+								};
+								let text: String = hover
+									.line
+									.chars()
+									.skip(start as usize)
+									.take(end.saturating_sub(start) as usize)
+									.collect();
+								if !text.trim().is_empty() {
+									write!(&mut hover.body, " {}", text.trim()).unwrap();
+								}
+							}
 						}
 					}
 				}
@@ -473,9 +859,66 @@ impl Server {
 						hover.body.push_str("\n");
 					}
 				}
+				if !hover.highlights.is_empty() {
+					if !hover.body.is_empty() {
+						hover.body.push('\n');
+					}
+					for h in hover.highlights.iter().take(10) {
+						let loc = Location::new(hover.url.clone(), h.range);
+						write!(&mut hover.body, "{}", location_to_plumb(&loc)).unwrap();
+						match h.kind {
+							Some(DocumentHighlightKind::READ) => hover.body.push_str(" (read)"),
+							Some(DocumentHighlightKind::WRITE) => hover.body.push_str(" (write)"),
+							_ => {}
+						}
+						hover.body.push('\n');
+					}
+				}
+				let hinted = inlay_hint_lines(
+					hover.first_hint_line,
+					&hover.hint_lines,
+					&hover.hints,
+					hover.encoding,
+				);
+				if !hinted.is_empty() {
+					if !hover.body.is_empty() {
+						hover.body.push('\n');
+					}
+					for line in hinted {
+						hover.body.push_str(&line);
+						hover.body.push('\n');
+					}
+				}
 			}
 		}
 	}
+	/// Returns the position encoding negotiated with a client.
+	fn client_encoding(&self, client_name: &str) -> Encoding {
+		let encoding = self
+			.capabilities
+			.get(client_name)
+			.and_then(|caps| caps.position_encoding.clone());
+		match encoding {
+			Some(e) if e == PositionEncodingKind::UTF8 => Encoding::Utf8,
+			Some(e) if e == PositionEncodingKind::UTF32 => Encoding::Utf32,
+			// UTF-16 is the default if the server doesn't pick one.
+			_ => Encoding::Utf16,
+		}
+	}
+	/// Returns the position encoding of the client handling url's file, which need not be
+	/// open.
+	fn url_encoding(&mut self, url: &Url) -> Encoding {
+		let path = url.path();
+		// Use the client that owns the file's windows, even when several match it.
+		let client_name = match self.files.get(path) {
+			Some(client_name) => client_name.clone(),
+			None => match self.lookup_client(path.to_string()) {
+				Ok(client_name) => client_name,
+				Err(_) => return Encoding::Utf16,
+			},
+		};
+		self.client_encoding(&client_name)
+	}
 	/// Returns the most recently focused win id for a filename.
 	fn winid_by_name(&self, filename: &str) -> Option<usize> {
 		self.focus_id.get(filename).cloned()
@@ -484,7 +927,12 @@ impl Server {
 		self.ws.get_mut(filename).and_then(|ids| ids.get_mut(id))
 	}
 	fn get_sw_by_name(&mut self, filename: &str) -> Option<(usize, &mut ServerWin)> {
-		let wid = self.winid_by_name(filename)?;
+		// Windows that have never been focused don't have an entry in focus_id, but they
+		// still need edits applied to them instead of their file on disk.
+		let wid = match self.winid_by_name(filename) {
+			Some(wid) => wid,
+			None => *self.ws.get(filename)?.keys().next()?,
+		};
 		let sw = self.get_sw_by_name_id(filename, &wid)?;
 		Some((wid, sw))
 	}
@@ -524,23 +972,148 @@ impl Server {
 			if caps.document_symbol_provider.is_some() {
 				body.push_str("[symbols] ");
 			}
+			if caps.workspace_symbol_provider.is_some() {
+				body.push_str("[wsymbols] ");
+			}
 			if caps.type_definition_provider.is_some() {
 				body.push_str("[typedef] ");
 			}
+			if caps.rename_provider.is_some() {
+				body.push_str("[rename] ");
+			}
+			if caps.document_highlight_provider.is_some() {
+				body.push_str("[highlight] ");
+			}
+			if caps.selection_range_provider.is_some() {
+				body.push_str("[expand] [shrink] ");
+			}
+			if caps.folding_range_provider.is_some() {
+				body.push_str("[outline] [region] ");
+			}
+			if caps.document_range_formatting_provider.is_some() {
+				body.push_str("[format] ");
+			}
+			if self
+				.snippet
+				.as_ref()
+				.is_some_and(|s| s.url.path() == file_name)
+			{
+				body.push_str("[tabstop] ");
+			}
+			if caps.call_hierarchy_provider.is_some() {
+				body.push_str("[callers] [callees] ");
+			}
+			if self.type_hierarchy.contains(client_name) {
+				body.push_str("[supertypes] [subtypes] ");
+			}
+			if caps
+				.experimental
+				.as_ref()
+				.and_then(|e| e.get("runnables"))
+				.is_some()
+			{
+				body.push_str("[runnables] ");
+			}
 			body.push('\n');
 		}
 		self.addr.push((body.len(), None));
 		write!(&mut body, "-----\n")?;
+		self.message_addrs.clear();
+		for (req_idx, req) in self.message_requests.iter().enumerate() {
+			write!(
+				&mut body,
+				"[{:?}] {}\n\t",
+				req.params.typ, req.params.message
+			)?;
+			for (action_idx, action) in req.params.actions.iter().flatten().enumerate() {
+				let start = body.len();
+				write!(&mut body, "[{}]", action.title)?;
+				self.message_addrs
+					.push((start, body.len(), req_idx, action_idx));
+				body.push(' ');
+			}
+			body.push('\n');
+		}
+		self.pending_edit_addrs.clear();
+		for (edit_idx, pending) in self.pending_edits.iter().enumerate() {
+			writeln!(
+				&mut body,
+				"[{}] needs confirmation:",
+				pending.label.as_deref().unwrap_or("workspace edit")
+			)?;
+			for (id, accepted) in &pending.confirmations {
+				if let Some(annotation) = pending
+					.edit
+					.change_annotations
+					.as_ref()
+					.and_then(|a| a.get(id))
+				{
+					write!(&mut body, "\t{}", annotation.label)?;
+					if let Some(description) = &annotation.description {
+						write!(&mut body, ": {}", description)?;
+					}
+				}
+				match accepted {
+					Some(true) => body.push_str(" (accepted)"),
+					Some(false) => body.push_str(" (rejected)"),
+					None => {
+						for (accept, title) in [(true, " [accept]"), (false, " [reject]")] {
+							let start = body.len() + 1;
+							body.push_str(title);
+							self.pending_edit_addrs.push((
+								start,
+								body.len(),
+								edit_idx,
+								id.clone(),
+								accept,
+							));
+						}
+					}
+				}
+				body.push('\n');
+			}
+		}
+		self.symbol_addrs.clear();
 		if !self.output.is_empty() {
+			body.push('\n');
 			// Only take the first 50 lines.
-			let output = self
-				.output
-				.trim()
-				.lines()
-				.take(50)
-				.collect::<Vec<_>>()
-				.join("\n");
-			write!(&mut body, "\n{}\n", output)?;
+			for (idx, line) in self.output.trim().lines().take(50).enumerate() {
+				let start = body.len();
+				writeln!(&mut body, "{}", line)?;
+				if self.output != self.symbol_lines.0 {
+					continue;
+				}
+				for &(_, symbol) in self.symbol_lines.1.iter().filter(|(l, _)| *l == idx) {
+					self.symbol_addrs.push((start, body.len(), symbol));
+				}
+			}
+		}
+		self.hierarchy_addrs.clear();
+		if let Some(tree) = &self.hierarchy {
+			write!(&mut body, "\n{}:\n", tree.kind.name())?;
+			for (idx, node) in tree.nodes.iter().enumerate() {
+				body.push_str(&"\t".repeat(node.depth));
+				let start = body.len();
+				body.push_str(if node.expanded { "[-]" } else { "[+]" });
+				self.hierarchy_addrs.push((start, body.len(), idx));
+				writeln!(
+					&mut body,
+					" {} ({:?}): {}",
+					node.item.name(),
+					node.item.kind(),
+					location_to_plumb(&node.item.location()),
+				)?;
+			}
+		}
+		self.runnable_addrs.clear();
+		if !self.runnables.is_empty() {
+			body.push_str("\nrunnables:\n");
+			for (idx, (_, runnable)) in self.runnables.iter().enumerate() {
+				let start = body.len();
+				write!(&mut body, "[{}]", runnable.label)?;
+				self.runnable_addrs.push((start, body.len(), idx));
+				body.push('\n');
+			}
 		}
 		if self.progress.len() > 0 {
 			body.push('\n');
@@ -548,10 +1121,17 @@ impl Server {
 		for (_, p) in &self.progress {
 			write!(&mut body, "{}\n", p)?;
 		}
-		if self.requests.len() > 0 {
+		// Servers may hold workspace/diagnostic open until something changes (long polling),
+		// so it isn't listed as pending.
+		let pending: Vec<_> = self
+			.requests
+			.iter()
+			.filter(|(_, (method, _))| method != WorkspaceDiagnosticRequest::METHOD)
+			.collect();
+		if !pending.is_empty() {
 			body.push('\n');
 		}
-		for (client_id, (method, url)) in &self.requests {
+		for (client_id, (method, url)) in pending {
 			write!(
 				&mut body,
 				"{}: {}: {}...\n",
@@ -562,9 +1142,16 @@ impl Server {
 		}
 		if !self.diags.is_empty() {
 			write!(&mut body, "-----\n")?;
-			for (_, ds) in self.diags.iter().take(5) {
+			for (path, ds) in self.diags.iter().take(5) {
 				for d in ds.iter().take(3) {
-					write!(&mut body, "{}\n", d)?;
+					write!(
+						&mut body,
+						"{}:{}: [{:?}] {}\n",
+						path,
+						d.range.start.line + 1,
+						d.severity.unwrap_or(lsp_types::DiagnosticSeverity::ERROR),
+						d.message.lines().next().unwrap_or(""),
+					)?;
 				}
 			}
 		}
@@ -573,24 +1160,218 @@ impl Server {
 			self.w.write(File::Addr, &format!(","))?;
 			self.w.write(File::Data, &body)?;
 			self.w.ctl("cleartag\nclean")?;
-			self.w.write(File::Tag, " Get")?;
+			self.w.write(File::Tag, " Get Diagnostics")?;
+		}
+		self.sync_diagnostics()
+	}
+	/// Writes every diagnostic to the diagnostics window if it is open.
+	fn sync_diagnostics(&mut self) -> Result<()> {
+		if self.diag_w.is_none() {
+			return Ok(());
+		}
+		let mut body = String::new();
+		let diags = &self.diags;
+		self.diag_sections
+			.retain(|path, _| diags.contains_key(path));
+		let paths: Vec<String> = self.diags.keys().cloned().collect();
+		for path in &paths {
+			let stamp = match self.ws.get(path).and_then(|ids| ids.values().next()) {
+				Some(sw) => TextStamp::Window(sw.version),
+				None => TextStamp::File(metadata(path).and_then(|md| md.modified()).ok()),
+			};
+			if let Some(section) = self.diag_sections.get(path) {
+				if section.stamp == stamp && section.diags == self.diags[path] {
+					body.push_str(&section.text);
+					continue;
+				}
+			}
+			let encoding = self.url_encoding(&Url::parse(&format!("file://{}", path))?);
+			let ds = &self.diags[path];
+			// Columns are converted to runes using the text the server last saw.
+			let text = match self.ws.get(path).and_then(|ids| ids.values().next()) {
+				Some(sw) => sw.synced.clone(),
+				None => read_to_string(path).unwrap_or_default(),
+			};
+			let nl = NlOffsets::with_encoding(text.as_bytes(), encoding)?;
+			let mut section = String::new();
+			let addr = |pos: &Position| {
+				let col =
+					nl.line_to_offset(pos.line, pos.character) - nl.line_to_offset(pos.line, 0);
+				format!("{}:{}:{}", path, pos.line + 1, col + 1)
+			};
+			for d in ds {
+				let severity = match d.severity.unwrap_or(DiagnosticSeverity::ERROR) {
+					DiagnosticSeverity::WARNING => "warning",
+					DiagnosticSeverity::INFORMATION => "info",
+					DiagnosticSeverity::HINT => "hint",
+					_ => "error",
+				};
+				write!(&mut section, "{}: {}", addr(&d.range.start), severity)?;
+				match &d.code {
+					Some(NumberOrString::Number(code)) => write!(&mut section, "[{}]", code)?,
+					Some(NumberOrString::String(code)) => write!(&mut section, "[{}]", code)?,
+					None => {}
+				}
+				if let Some(source) = &d.source {
+					write!(&mut section, " {}", source)?;
+				}
+				let mut lines = d.message.lines();
+				writeln!(&mut section, ": {}", lines.next().unwrap_or(""))?;
+				for line in lines {
+					writeln!(&mut section, "\t{}", line)?;
+				}
+				for related in d.related_information.iter().flatten() {
+					let loc = &related.location;
+					if loc.uri.path() == path {
+						writeln!(
+							&mut section,
+							"\t{}: {}",
+							addr(&loc.range.start),
+							related.message
+						)?;
+					} else {
+						writeln!(
+							&mut section,
+							"\t{}: {}",
+							location_to_plumb(loc),
+							related.message
+						)?;
+					}
+				}
+			}
+			body.push_str(&section);
+			let diags = ds.clone();
+			self.diag_sections.insert(
+				path.clone(),
+				DiagSection {
+					stamp,
+					diags,
+					text: section,
+				},
+			);
+		}
+		if body == self.diag_body {
+			return Ok(());
+		}
+		let w = self.diag_w.as_mut().unwrap();
+		w.write(File::Addr, ",")?;
+		w.write(File::Data, &body)?;
+		w.ctl("clean")?;
+		self.diag_body = body;
+		Ok(())
+	}
+	/// Opens the diagnostics window, or does nothing if it is already open.
+	fn open_diagnostics(&mut self) -> Result<()> {
+		if self.diag_w.is_some() {
+			return Ok(());
+		}
+		let mut w = Win::new()?;
+		w.name("acre/diagnostics")?;
+		self.diag_w = Some(w);
+		self.diag_body.clear();
+		self.sync_diagnostics()
+	}
+	/// Pulls the diagnostics of a document from servers that support pull diagnostics.
+	fn pull_diagnostics(&mut self, client_name: &str, url: Url) -> Result<()> {
+		let identifier = match self
+			.capabilities
+			.get(client_name)
+			.and_then(diagnostic_options)
+		{
+			Some(opts) => opts.identifier.clone(),
+			None => return Ok(()),
+		};
+		let previous_result_id = self.diag_result_ids.get(url.path()).cloned();
+		self.send_request::<DocumentDiagnosticRequest>(
+			client_name,
+			url.clone(),
+			DocumentDiagnosticParams {
+				text_document: TextDocumentIdentifier::new(url),
+				identifier,
+				previous_result_id,
+				work_done_progress_params,
+				partial_result_params,
+			},
+		)?;
+		Ok(())
+	}
+	/// Pulls the diagnostics of the whole workspace if the server supports it and isn't
+	/// already working on a previous pull.
+	fn pull_workspace_diagnostics(&mut self, client_name: &str) -> Result<()> {
+		let identifier = match self
+			.capabilities
+			.get(client_name)
+			.and_then(diagnostic_options)
+		{
+			Some(opts) if opts.workspace_diagnostics => opts.identifier.clone(),
+			_ => return Ok(()),
+		};
+		if self.requests.iter().any(|(id, (method, _))| {
+			id.client_name == client_name && method == WorkspaceDiagnosticRequest::METHOD
+		}) {
+			return Ok(());
 		}
+		let mut previous_result_ids = vec![];
+		for (path, value) in &self.diag_result_ids {
+			previous_result_ids.push(PreviousResultId {
+				uri: Url::parse(&format!("file://{}", path))?,
+				value: value.clone(),
+			});
+		}
+		self.send_request::<WorkspaceDiagnosticRequest>(
+			client_name,
+			Url::parse("file:///").unwrap(),
+			WorkspaceDiagnosticParams {
+				identifier,
+				previous_result_ids,
+				work_done_progress_params,
+				partial_result_params,
+			},
+		)?;
 		Ok(())
 	}
+	/// Stores a pulled diagnostics report for path.
+	fn set_diagnostics_report(&mut self, path: &str, report: DocumentDiagnosticReportKind) {
+		match report {
+			DocumentDiagnosticReportKind::Full(report) => {
+				match report.result_id {
+					Some(id) => self.diag_result_ids.insert(path.to_string(), id),
+					None => self.diag_result_ids.remove(path),
+				};
+				if report.items.is_empty() {
+					self.diags.remove(path);
+				} else {
+					self.diags.insert(path.to_string(), report.items);
+				}
+			}
+			// The previously stored diagnostics are still current.
+			DocumentDiagnosticReportKind::Unchanged(_) => {}
+		}
+	}
 	fn init_win(&mut self, filename: String, winid: usize) -> Result<bool> {
 		let client_name = match self.lookup_client(filename.clone()) {
 			Ok(n) => n,
 			Err(_) => bail!("no client for {}", filename),
 		};
+		let encoding = self.client_encoding(&client_name);
 		let need_open = !self.ws.contains_key(&filename);
 		if need_open {
 			self.ws.insert(filename.clone(), HashMap::new());
 		}
 		let ids = self.ws.get_mut(&filename).unwrap();
+		// Keep existing windows so their synced text and version survive.
+		if ids.contains_key(&winid) {
+			return Ok(need_open);
+		}
 		let mut fsys = FSYS.lock().unwrap();
 		let ctl = fsys.open(format!("{}/ctl", winid).as_str(), OpenMode::RDWR)?;
 		let w = Win::open(&mut fsys, winid, ctl)?;
-		let sw = ServerWin::new(filename, w, client_name)?;
+		let mut sw = ServerWin::new(filename, w, client_name, encoding)?;
+		// A zerox'd window shares its document with the other windows of the file.
+		if let Some(other) = ids.values().next() {
+			sw.version = other.version;
+			sw.synced = other.synced.clone();
+		}
 		ids.insert(winid, sw);
 		Ok(need_open)
 	}
@@ -634,6 +1415,7 @@ impl Server {
 					None => continue,
 				};
 				let (version, text) = sw.text()?;
+				sw.synced = text.clone();
 				let url = sw.url.clone();
 				let client_name = sw.client.clone();
 				drop(sw);
@@ -664,30 +1446,129 @@ impl Server {
 				},
 			)?;
 		}
+
+		// Apply edits that were waiting for a plumbed window to open.
+		let opened: Vec<Url> = self
+			.plumbed_edits
+			.keys()
+			.filter(|url| self.ws.contains_key(url.path()))
+			.cloned()
+			.collect();
+		for url in opened {
+			self.apply_plumbed(&url)?;
+		}
+		Ok(())
+	}
+	/// Applies the plumbed edits of url to its window, or to disk if it hasn't opened, and
+	/// answers the edit_replies that were waiting on them.
+	fn apply_plumbed(&mut self, url: &Url) -> Result<()> {
+		let pending = match self.plumbed_edits.remove(url) {
+			Some(v) => v.edits,
+			None => return Ok(()),
+		};
+		let result = self.write_plumbed(url, pending);
+		for reply in &mut self.edit_replies {
+			if reply.waiting.remove(url) {
+				if let Err(err) = &result {
+					reply
+						.err
+						.get_or_insert_with(|| format!("{}: {}", url.path(), err));
+				}
+			}
+		}
+		let (done, waiting) = std::mem::take(&mut self.edit_replies)
+			.into_iter()
+			.partition(|reply| reply.waiting.is_empty());
+		self.edit_replies = waiting;
+		for reply in done {
+			let applied = match reply.err {
+				Some(err) => Err(Error::msg(err)),
+				None => Ok(()),
+			};
+			self.reply_edit(reply.client_name, reply.id, reply.label, applied)?;
+		}
+		result
+	}
+	fn write_plumbed(
+		&mut self,
+		url: &Url,
+		pending: Vec<(InsertTextFormat, Vec<TextEdit>)>,
+	) -> Result<()> {
+		if self.get_sw_by_url(url).is_some() {
+			for (format, edits) in pending {
+				self.apply_text_edits(url, format, &edits)?;
+			}
+		} else {
+			let encoding = self.url_encoding(url);
+			for (_, edits) in pending {
+				apply_file_edits(url.path(), &edits, encoding)?;
+			}
+		}
 		Ok(())
 	}
+	/// Returns how long until the oldest plumbed edits time out, if there are any.
+	fn plumb_timeout(&self) -> Option<Duration> {
+		self.plumbed_edits
+			.values()
+			.map(|p| PLUMB_TIMEOUT.saturating_sub(p.since.elapsed()))
+			.min()
+	}
+	/// Writes plumbed edits whose windows haven't opened after PLUMB_TIMEOUT to disk.
+	fn expire_plumbed_edits(&mut self) {
+		let expired: Vec<Url> = self
+			.plumbed_edits
+			.iter()
+			.filter(|(_, p)| p.since.elapsed() >= PLUMB_TIMEOUT)
+			.map(|(url, _)| url.clone())
+			.collect();
+		let mut summary = vec![];
+		for url in expired {
+			summary.push(match self.apply_plumbed(&url) {
+				Ok(()) => format!("{}: window did not open, written to disk", url.path()),
+				Err(err) => format!("{}: window did not open: {}", url.path(), err),
+			});
+		}
+		if !summary.is_empty() {
+			summary.sort();
+			self.output = summary.join("\n");
+		}
+	}
 	fn lsp_msg(&mut self, client_name: String, orig_msg: Vec<u8>) -> Result<()> {
 		let msg: lsp::DeMessage = serde_json::from_slice(&orig_msg)?;
-		if msg.id.is_some() && msg.error.is_some() {
-			self.lsp_error(
-				ClientId::new(client_name, msg.id.unwrap()),
-				msg.error.unwrap(),
-			)
-		} else if msg.id.is_some() && msg.method.is_some() {
-			self.lsp_request(msg)
-		} else if msg.id.is_some() {
-			self.lsp_response(ClientId::new(client_name, msg.id.unwrap()), msg, &orig_msg)
-		} else if msg.method.is_some() {
-			self.lsp_notification(client_name, msg.method.unwrap(), msg.params)
-		} else {
-			panic!(
+		match (msg.id.clone(), msg.method.clone()) {
+			(Some(id), Some(method)) => self.lsp_request(client_name, id, method, msg.params),
+			// We only send numeric ids, so any response must have one.
+			(Some(NumberOrString::Number(id)), None) => {
+				let client_id = ClientId::new(client_name, id as usize);
+				if let Some(err) = msg.error {
+					self.lsp_error(client_id, err)
+				} else {
+					self.lsp_response(client_id, msg, &orig_msg)
+				}
+			}
+			(None, Some(method)) => self.lsp_notification(client_name, method, msg.params),
+			_ => panic!(
 				"unknown message {}",
 				std::str::from_utf8(&orig_msg).unwrap()
-			);
+			),
 		}
 	}
 	fn lsp_error(&mut self, client_id: ClientId, err: lsp::ResponseError) -> Result<()> {
 		self.requests.remove(&client_id);
+		self.renames.remove(&client_id);
+		self.symbol_resolves.remove(&client_id);
+		self.hierarchy_expands.remove(&client_id);
+		self.lens_resolves.remove(&client_id);
+		self.selection_ranges.remove(&client_id);
+		self.folding_ranges.remove(&client_id);
+		// Don't leave the header of a hierarchy that failed to load.
+		if self
+			.hierarchy
+			.as_ref()
+			.is_some_and(|tree| tree.nodes.is_empty())
+		{
+			self.hierarchy = None;
+		}
 		self.output = format!("lsp error: {}", err.message);
 		Ok(())
 	}
@@ -704,6 +1585,17 @@ impl Server {
 		let result = match msg.result {
 			Some(v) => v,
 			None => {
+				if self.renames.remove(&client_id).is_some() {
+					self.output = "cannot rename this element".to_string();
+				}
+				self.symbol_resolves.remove(&client_id);
+				self.hierarchy_expands.remove(&client_id);
+				self.lens_resolves.remove(&client_id);
+				self.selection_ranges.remove(&client_id);
+				self.folding_ranges.remove(&client_id);
+				if typ == CallHierarchyPrepare::METHOD || typ == TypeHierarchyPrepare::METHOD {
+					return self.set_hierarchy_roots(vec![]);
+				}
 				// Ignore empty results. Unsure if/how we should report this to a user.
 				return Ok(());
 			}
@@ -716,13 +1608,21 @@ impl Server {
 					InitializedParams {},
 				)?;
 				self.capabilities
-					.insert(client_id.client_name, msg.capabilities);
+					.insert(client_id.client_name.clone(), msg.capabilities);
+				let raw = serde_json::from_str::<serde_json::Value>(result.get())?;
+				match raw["capabilities"]["typeHierarchyProvider"] {
+					serde_json::Value::Null | serde_json::Value::Bool(false) => {}
+					_ => {
+						self.type_hierarchy.insert(client_id.client_name.clone());
+					}
+				}
 				self.sync_windows()?;
+				self.pull_workspace_diagnostics(&client_id.client_name)?;
 			}
 			GotoDefinition::METHOD => {
 				let msg = serde_json::from_str::<Option<GotoDefinitionResponse>>(result.get())?;
 				if let Some(msg) = msg {
-					goto_definition(&msg)?;
+					self.goto_definition(msg)?;
 				}
 			}
 			HoverRequest::METHOD => {
@@ -752,26 +1652,8 @@ impl Server {
 			}
 			References::METHOD => {
 				let msg = serde_json::from_str::<Option<Vec<Location>>>(result.get())?;
-				if let Some(mut msg) = msg {
-					msg.sort_by(cmp_location);
-					let mut o = Vec::new();
-					let mut files: HashMap<Url, String> = HashMap::new();
-					for x in msg {
-						o.push(location_to_plumb(&x));
-						let text = files.entry(x.uri.clone()).or_insert_with(|| {
-							match self.get_sw_by_url(&x.uri) {
-								Some((_, win)) => win.text().unwrap_or((0, "".into())).1,
-								None => read_to_string(x.uri.path()).unwrap_or("".into()),
-							}
-						});
-						if let Some(line) = text.lines().nth(x.range.start.line.try_into().unwrap())
-						{
-							o.push(format!("\t{}", line.trim()));
-						}
-					}
-					if o.len() > 0 {
-						self.output = o.join("\n");
-					}
+				if let Some(msg) = msg {
+					self.locations_output(msg);
 				}
 			}
 			DocumentSymbolRequest::METHOD => {
@@ -844,10 +1726,82 @@ impl Server {
 					}
 				}
 			}
-			SignatureHelpRequest::METHOD => {
-				let msg = serde_json::from_str::<Option<SignatureHelp>>(result.get())?;
-				if let Some(msg) = msg {
-					let sig = match msg.active_signature {
+			WorkspaceSymbolRequest::METHOD => {
+				let msg = serde_json::from_str::<Option<WorkspaceSymbolResponse>>(result.get())?;
+				let symbols = match msg {
+					Some(WorkspaceSymbolResponse::Flat(sis)) => sis
+						.into_iter()
+						.map(|si| WorkspaceSymbol {
+							name: si.name,
+							kind: si.kind,
+							tags: si.tags,
+							container_name: si.container_name,
+							location: OneOf::Left(si.location),
+							data: None,
+						})
+						.collect(),
+					Some(WorkspaceSymbolResponse::Nested(wss)) => wss,
+					None => vec![],
+				};
+				self.workspace_symbols = symbols;
+				self.symbol_client = client_id.client_name.clone();
+				self.symbol_resolves.clear();
+				self.workspace_symbols_output();
+			}
+			WorkspaceSymbolResolve::METHOD => {
+				let msg = serde_json::from_str::<WorkspaceSymbol>(result.get())?;
+				if let Some(idx) = self.symbol_resolves.remove(&client_id) {
+					// The symbol was clicked, so go to it.
+					if let OneOf::Left(loc) = &msg.location {
+						plumb_location(location_to_plumb(loc))?;
+					}
+					self.workspace_symbols[idx] = msg;
+					self.workspace_symbols_output();
+				}
+			}
+			CallHierarchyPrepare::METHOD => {
+				let msg = serde_json::from_str::<Option<Vec<CallHierarchyItem>>>(result.get())?;
+				let items = msg.unwrap_or_default().into_iter().map(HierarchyItem::Call);
+				self.set_hierarchy_roots(items.collect())?;
+			}
+			CallHierarchyIncomingCalls::METHOD => {
+				let msg =
+					serde_json::from_str::<Option<Vec<CallHierarchyIncomingCall>>>(result.get())?;
+				let items = msg.unwrap_or_default().into_iter();
+				let items = items.map(|call| HierarchyItem::Call(call.from));
+				self.set_hierarchy_children(&client_id, items.collect());
+			}
+			CallHierarchyOutgoingCalls::METHOD => {
+				let msg =
+					serde_json::from_str::<Option<Vec<CallHierarchyOutgoingCall>>>(result.get())?;
+				let items = msg.unwrap_or_default().into_iter();
+				let items = items.map(|call| HierarchyItem::Call(call.to));
+				self.set_hierarchy_children(&client_id, items.collect());
+			}
+			lsp::Runnables::METHOD => {
+				let msg = serde_json::from_str::<Vec<lsp::Runnable>>(result.get())?;
+				self.runnables = msg
+					.into_iter()
+					.map(|runnable| (client_id.client_name.clone(), runnable))
+					.collect();
+				if self.runnables.is_empty() {
+					self.output = "no runnables here".to_string();
+				}
+			}
+			TypeHierarchyPrepare::METHOD => {
+				let msg = serde_json::from_str::<Option<Vec<TypeHierarchyItem>>>(result.get())?;
+				let items = msg.unwrap_or_default().into_iter().map(HierarchyItem::Type);
+				self.set_hierarchy_roots(items.collect())?;
+			}
+			TypeHierarchySupertypes::METHOD | TypeHierarchySubtypes::METHOD => {
+				let msg = serde_json::from_str::<Option<Vec<TypeHierarchyItem>>>(result.get())?;
+				let items = msg.unwrap_or_default().into_iter().map(HierarchyItem::Type);
+				self.set_hierarchy_children(&client_id, items.collect());
+			}
+			SignatureHelpRequest::METHOD => {
+				let msg = serde_json::from_str::<Option<SignatureHelp>>(result.get())?;
+				if let Some(msg) = msg {
+					let sig = match msg.active_signature {
 						Some(i) => i,
 						None => 0,
 					};
@@ -865,16 +1819,166 @@ impl Server {
 			}
 			CodeLensRequest::METHOD => {
 				let msg = serde_json::from_str::<Option<Vec<CodeLens>>>(result.get())?;
+				let line = match &self.current_hover {
+					Some(hover) if hover.url == url => hover.range.start.line,
+					_ => return Ok(()),
+				};
+				// Only the lenses of the cursor line are shown.
+				let lens: Vec<CodeLens> = msg
+					.unwrap_or_default()
+					.into_iter()
+					.filter(|lens| lens.range.start.line <= line && line <= lens.range.end.line)
+					.collect();
+				let resolve = match self.capabilities.get(&client_id.client_name) {
+					Some(ServerCapabilities {
+						code_lens_provider: Some(opts),
+						..
+					}) => opts.resolve_provider.unwrap_or(false),
+					_ => false,
+				};
+				self.lens_resolves.clear();
+				if resolve {
+					for (idx, lens) in lens.iter().enumerate() {
+						if lens.command.is_none() {
+							let msg_id = self.send_request::<CodeLensResolve>(
+								&client_id.client_name,
+								url.clone(),
+								lens.clone(),
+							)?;
+							self.lens_resolves
+								.insert(ClientId::new(&client_id.client_name, msg_id), idx);
+						}
+					}
+				}
+				self.set_hover(&url, |hover| {
+					hover.lens = lens;
+				});
+			}
+			SelectionRangeRequest::METHOD => {
+				let msg = serde_json::from_str::<Option<Vec<SelectionRange>>>(result.get())?;
+				let (expand, dot) = match self.selection_ranges.remove(&client_id) {
+					Some(v) => v,
+					None => return Ok(()),
+				};
+				// Ranges from the innermost to the outermost.
+				let mut ranges = vec![];
+				let mut next = msg.and_then(|msg| msg.into_iter().next());
+				while let Some(sr) = next {
+					ranges.push(sr.range);
+					next = sr.parent.map(|p| *p);
+				}
+				let range = if expand {
+					ranges
+						.iter()
+						.find(|r| **r != dot && range_contains(r, &dot))
+				} else {
+					ranges
+						.iter()
+						.rev()
+						.find(|r| **r != dot && range_contains(&dot, r))
+				};
+				if let (Some(range), Some((_, sw))) = (range, self.get_sw_by_url(&url)) {
+					sw.select(range)?;
+				}
+			}
+			FoldingRangeRequest::METHOD => {
+				let msg = serde_json::from_str::<Option<Vec<FoldingRange>>>(result.get())?;
+				let dot = match self.folding_ranges.remove(&client_id) {
+					Some(dot) => dot,
+					None => return Ok(()),
+				};
+				let mut folds = msg.unwrap_or_default();
+				// Sort outer regions before the regions they contain.
+				folds.sort_by(|a, b| {
+					a.start_line
+						.cmp(&b.start_line)
+						.then(b.end_line.cmp(&a.end_line))
+				});
+				// Whole lines, since that is what acme addresses and folding ranges both favour.
+				let region = |f: &FoldingRange| {
+					Range::new(
+						Position::new(f.start_line, 0),
+						Position::new(f.end_line + 1, 0),
+					)
+				};
+				match dot {
+					Some(dot) => {
+						let range = folds
+							.iter()
+							.rev()
+							.map(region)
+							.find(|r| *r != dot && range_contains(r, &dot));
+						if let (Some(range), Some((_, sw))) = (range, self.get_sw_by_url(&url)) {
+							sw.select(&range)?;
+						}
+					}
+					None => {
+						let text = match self.get_sw_by_url(&url) {
+							Some((_, sw)) => sw.synced.clone(),
+							None => read_to_string(url.path()).unwrap_or_default(),
+						};
+						let lines: Vec<&str> = text.lines().collect();
+						let mut o = vec![];
+						let mut parents: Vec<u32> = vec![];
+						for f in &folds {
+							while parents.last().is_some_and(|end| *end < f.start_line) {
+								parents.pop();
+							}
+							let label = match (&f.collapsed_text, &f.kind) {
+								(Some(text), _) => text.clone(),
+								(None, Some(FoldingRangeKind::Comment)) => "comment".into(),
+								(None, Some(FoldingRangeKind::Imports)) => "imports".into(),
+								_ => lines
+									.get(f.start_line as usize)
+									.map_or("".into(), |l| l.trim().to_string()),
+							};
+							o.push(format!(
+								"{}{}:{},{} {}",
+								"\t".repeat(parents.len()),
+								url.path(),
+								f.start_line + 1,
+								f.end_line + 1,
+								label
+							));
+							parents.push(f.end_line);
+						}
+						self.output = if o.is_empty() {
+							"no folding ranges".to_string()
+						} else {
+							o.join("\n")
+						};
+					}
+				}
+			}
+			DocumentHighlightRequest::METHOD => {
+				let msg = serde_json::from_str::<Option<Vec<DocumentHighlight>>>(result.get())?;
+				if let Some(mut msg) = msg {
+					msg.sort_by(|a, b| cmp_range(&a.range, &b.range));
+					self.set_hover(&url, |hover| {
+						hover.highlights = msg;
+					});
+				}
+			}
+			InlayHintRequest::METHOD => {
+				let msg = serde_json::from_str::<Option<Vec<InlayHint>>>(result.get())?;
 				if let Some(msg) = msg {
 					self.set_hover(&url, |hover| {
-						hover.lens = msg;
+						hover.hints = msg;
 					});
 				}
 			}
+			ResolveCompletionItem::METHOD => {
+				let msg = serde_json::from_str::<CompletionItem>(result.get())?;
+				self.apply_completion(&client_id.client_name, &url, msg)?;
+			}
 			CodeLensResolve::METHOD => {
-				let msg = serde_json::from_str::<Option<Vec<CodeLens>>>(result.get())?;
-				if let Some(msg) = msg {
-					eprintln!("code resolve msg: {:?}", msg);
+				let msg = serde_json::from_str::<CodeLens>(result.get())?;
+				if let Some(idx) = self.lens_resolves.remove(&client_id) {
+					self.set_hover(&url, |hover| {
+						if let Some(lens) = hover.lens.get_mut(idx) {
+							*lens = msg;
+						}
+					});
 				}
 			}
 			CodeActionRequest::METHOD => {
@@ -900,24 +2004,37 @@ impl Server {
 			CodeActionResolveRequest::METHOD => {
 				let msg = serde_json::from_str::<Option<CodeAction>>(result.get())?;
 				if let Some(msg) = msg {
+					if msg.edit.is_none() && msg.command.is_none() {
+						eprintln!("unexpected CodeActionResolveRequest response: {:#?}", msg);
+					}
 					if let Some(edit) = msg.edit {
 						self.apply_workspace_edit(&edit)?;
-					} else {
-						eprintln!("unexpected CodeActionResolveRequest response: {:#?}", msg);
+					}
+					if let Some(cmd) = msg.command {
+						self.execute_command(&client_id.client_name, url, cmd)?;
 					}
 				}
 			}
+			ExecuteCommand::METHOD => {
+				// Any edits made by the command arrive as a workspace/applyEdit request.
+			}
 			Completion::METHOD => {
 				let msg = serde_json::from_str::<Option<CompletionResponse>>(result.get())?;
 				if let Some(msg) = msg {
 					self.set_hover(&url, move |hover| {
-						hover.completion = match msg {
-							CompletionResponse::Array(cis) => cis,
-							CompletionResponse::List(cls) => cls.items,
+						(hover.completion, hover.completion_incomplete) = match msg {
+							CompletionResponse::Array(cis) => (cis, false),
+							CompletionResponse::List(cls) => (cls.items, cls.is_incomplete),
 						};
 					});
 				}
 			}
+			RangeFormatting::METHOD | OnTypeFormatting::METHOD => {
+				let msg = serde_json::from_str::<Option<Vec<TextEdit>>>(result.get())?;
+				if let Some(msg) = msg {
+					self.apply_text_edits(&url, InsertTextFormat::PLAIN_TEXT, &msg)?;
+				}
+			}
 			Formatting::METHOD => {
 				let msg = serde_json::from_str::<Option<Vec<TextEdit>>>(result.get())?;
 				if let Some(msg) = msg {
@@ -941,6 +2058,7 @@ impl Server {
 								context: CodeActionContext {
 									diagnostics: vec![],
 									only: Some(actions),
+									trigger_kind: None,
 								},
 								work_done_progress_params: WorkDoneProgressParams {
 									work_done_token: None,
@@ -957,13 +2075,67 @@ impl Server {
 			GotoImplementation::METHOD => {
 				let msg = serde_json::from_str::<Option<GotoImplementationResponse>>(result.get())?;
 				if let Some(msg) = msg {
-					goto_definition(&msg)?;
+					self.goto_definition(msg)?;
 				}
 			}
 			GotoTypeDefinition::METHOD => {
 				let msg = serde_json::from_str::<Option<GotoTypeDefinitionResponse>>(result.get())?;
 				if let Some(msg) = msg {
-					goto_definition(&msg)?;
+					self.goto_definition(msg)?;
+				}
+			}
+			PrepareRenameRequest::METHOD => {
+				let msg = serde_json::from_str::<Option<PrepareRenameResponse>>(result.get())?;
+				let params = self.renames.remove(&client_id);
+				match (msg, params) {
+					(Some(_), Some(params)) => {
+						self.send_request::<Rename>(&client_id.client_name, url, params)?;
+					}
+					(None, Some(_)) => {
+						self.output = "cannot rename this element".to_string();
+					}
+					_ => {}
+				}
+			}
+			DocumentDiagnosticRequest::METHOD => {
+				let msg = serde_json::from_str::<DocumentDiagnosticReportResult>(result.get())?;
+				let related = match msg {
+					DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(r)) => {
+						self.set_diagnostics_report(
+							url.path(),
+							DocumentDiagnosticReportKind::Full(r.full_document_diagnostic_report),
+						);
+						r.related_documents
+					}
+					DocumentDiagnosticReportResult::Report(
+						DocumentDiagnosticReport::Unchanged(r),
+					) => r.related_documents,
+					DocumentDiagnosticReportResult::Partial(r) => r.related_documents,
+				};
+				for (url, report) in related.into_iter().flatten() {
+					self.set_diagnostics_report(url.path(), report);
+				}
+			}
+			WorkspaceDiagnosticRequest::METHOD => {
+				let msg = serde_json::from_str::<WorkspaceDiagnosticReportResult>(result.get())?;
+				let items = match msg {
+					WorkspaceDiagnosticReportResult::Report(r) => r.items,
+					WorkspaceDiagnosticReportResult::Partial(r) => r.items,
+				};
+				for item in items {
+					match item {
+						WorkspaceDocumentDiagnosticReport::Full(r) => self.set_diagnostics_report(
+							r.uri.path(),
+							DocumentDiagnosticReportKind::Full(r.full_document_diagnostic_report),
+						),
+						WorkspaceDocumentDiagnosticReport::Unchanged(_) => {}
+					}
+				}
+			}
+			Rename::METHOD => {
+				let msg = serde_json::from_str::<Option<WorkspaceEdit>>(result.get())?;
+				if let Some(msg) = msg {
+					self.apply_workspace_edit(&msg)?;
 				}
 			}
 			SemanticTokensRangeRequest::METHOD => {
@@ -975,13 +2147,24 @@ impl Server {
 							// Not sure why there would be more than 1 result, but we only need to care
 							// about a single one anyway.
 							if let Some(token) = tokens.data.into_iter().next() {
+								let encoding = self.client_encoding(&client_id.client_name);
 								self.set_hover(&url, |hover| {
+									let nl = match NlOffsets::with_encoding(
+										hover.line.as_bytes(),
+										encoding,
+									) {
+										Ok(nl) => nl,
+										Err(_) => return,
+									};
+									let start = nl.line_to_offset(0, token.delta_start);
+									let end =
+										nl.line_to_offset(0, token.delta_start + token.length);
 									hover.token = Some(
 										hover
 											.line
 											.chars()
-											.skip(token.delta_start as usize)
-											.take(token.length as usize)
+											.skip(start as usize)
+											.take((end - start) as usize)
 											.collect(),
 									);
 								});
@@ -1008,20 +2191,12 @@ impl Server {
 			}
 			PublishDiagnostics::METHOD => {
 				let msg: PublishDiagnosticsParams = serde_json::from_str(params.unwrap().get())?;
-				let mut v = vec![];
 				let path = msg.uri.path();
-				// Cap diagnostic length.
-				for p in msg.diagnostics.iter().take(5) {
-					let msg = p.message.lines().next().unwrap_or("");
-					v.push(format!(
-						"{}:{}: [{:?}] {}",
-						path,
-						p.range.start.line + 1,
-						p.severity.unwrap_or(lsp_types::DiagnosticSeverity::ERROR),
-						msg,
-					));
+				if msg.diagnostics.is_empty() {
+					self.diags.remove(path);
+				} else {
+					self.diags.insert(path.to_string(), msg.diagnostics);
 				}
-				self.diags.insert(path.to_string(), v);
 			}
 			ShowMessage::METHOD => {
 				let msg: ShowMessageParams = serde_json::from_str(params.unwrap().get())?;
@@ -1060,45 +2235,459 @@ impl Server {
 		}
 		Ok(())
 	}
-	fn lsp_request(&mut self, msg: lsp::DeMessage) -> Result<()> {
-		eprintln!("unknown request {:?}", msg);
+	fn lsp_request(
+		&mut self,
+		client_name: String,
+		id: NumberOrString,
+		method: String,
+		params: Option<Box<serde_json::value::RawValue>>,
+	) -> Result<()> {
+		let params = params
+			.map(|p| p.get().to_string())
+			.unwrap_or_else(|| "null".to_string());
+		match method.as_str() {
+			WorkspaceConfiguration::METHOD => {
+				let msg: ConfigurationParams = serde_json::from_str(&params)?;
+				let options = self
+					.config
+					.servers
+					.get(&client_name)
+					.and_then(|s| s.options.clone())
+					.unwrap_or(Value::Null);
+				let result = msg
+					.items
+					.iter()
+					.map(|item| config_section(&client_name, &options, item.section.as_deref()))
+					.collect();
+				self.respond::<WorkspaceConfiguration>(&client_name, id, result)
+			}
+			WorkDoneProgressCreate::METHOD => {
+				self.respond::<WorkDoneProgressCreate>(&client_name, id, ())
+			}
+			RegisterCapability::METHOD => self.respond::<RegisterCapability>(&client_name, id, ()),
+			UnregisterCapability::METHOD => {
+				self.respond::<UnregisterCapability>(&client_name, id, ())
+			}
+			ApplyWorkspaceEdit::METHOD => {
+				let msg: ApplyWorkspaceEditParams = serde_json::from_str(&params)?;
+				let request = Some((client_name.clone(), id.clone()));
+				if self.queue_confirmation(&msg.edit, msg.label.clone(), request) {
+					return Ok(());
+				}
+				let result = self.write_workspace_edit(&msg.edit, &HashSet::new());
+				self.edit_written(client_name, id, msg.label, &msg.edit, result)
+			}
+			ShowMessageRequest::METHOD => {
+				let msg: ShowMessageRequestParams = serde_json::from_str(&params)?;
+				if msg.actions.as_ref().map(|a| a.is_empty()).unwrap_or(true) {
+					// Nothing to choose from, so it is the same as a ShowMessage.
+					self.output = format!("[{:?}] {}", msg.typ, msg.message);
+					return self.respond::<ShowMessageRequest>(&client_name, id, None);
+				}
+				self.message_requests.push(MessageRequest {
+					client_name,
+					id,
+					params: msg,
+				});
+				Ok(())
+			}
+			CodeLensRefresh::METHOD => {
+				self.respond::<CodeLensRefresh>(&client_name, id, ())?;
+				let url = match &self.current_hover {
+					Some(hover) if hover.client_name == client_name => hover.url.clone(),
+					_ => return Ok(()),
+				};
+				self.send_request::<CodeLensRequest>(
+					&client_name,
+					url.clone(),
+					CodeLensParams {
+						text_document: TextDocumentIdentifier::new(url),
+						work_done_progress_params,
+						partial_result_params,
+					},
+				)?;
+				Ok(())
+			}
+			WorkspaceDiagnosticRefresh::METHOD => {
+				self.respond::<WorkspaceDiagnosticRefresh>(&client_name, id, ())?;
+				self.diag_result_ids.clear();
+				if let Some((_, sw)) = self.get_sw_by_name(&self.focus.clone()) {
+					let url = sw.url.clone();
+					if sw.client == client_name {
+						self.pull_diagnostics(&client_name, url)?;
+					}
+				}
+				self.pull_workspace_diagnostics(&client_name)
+			}
+			_ => {
+				eprintln!("unknown request {}", method);
+				self.clients.get_mut(&client_name).unwrap().respond_error(
+					id,
+					lsp::METHOD_NOT_FOUND,
+					format!("unsupported method: {}", method),
+				)
+			}
+		}
+	}
+	fn respond<R: Request>(
+		&mut self,
+		client_name: &str,
+		id: NumberOrString,
+		result: R::Result,
+	) -> Result<()> {
+		let client = self.clients.get_mut(client_name).unwrap();
+		client.respond::<R>(id, result)
+	}
+	/// Returns the reply to a workspace/applyEdit request, showing any error in the output.
+	fn apply_edit_response(
+		&mut self,
+		label: Option<String>,
+		result: Result<()>,
+	) -> ApplyWorkspaceEditResponse {
+		match result {
+			Ok(()) => ApplyWorkspaceEditResponse {
+				applied: true,
+				failure_reason: None,
+				failed_change: None,
+			},
+			Err(err) => {
+				let label = label.unwrap_or_else(|| "workspace edit".to_string());
+				self.output = format!("{} failed: {}", label, err);
+				ApplyWorkspaceEditResponse {
+					applied: false,
+					failure_reason: Some(err.to_string()),
+					failed_change: None,
+				}
+			}
+		}
+	}
+	/// Answers a workspace/applyEdit request whose edit was written with result, waiting
+	/// until any edits to plumbed files have been applied.
+	fn edit_written(
+		&mut self,
+		client_name: String,
+		id: NumberOrString,
+		label: Option<String>,
+		edit: &WorkspaceEdit,
+		result: Result<()>,
+	) -> Result<()> {
+		let waiting: HashSet<Url> = edit_urls(edit)
+			.into_iter()
+			.filter(|url| self.plumbed_edits.contains_key(url))
+			.collect();
+		if result.is_err() || waiting.is_empty() {
+			return self.reply_edit(client_name, id, label, result);
+		}
+		self.edit_replies.push(EditReply {
+			client_name,
+			id,
+			label,
+			waiting,
+			err: None,
+		});
 		Ok(())
 	}
+	fn reply_edit(
+		&mut self,
+		client_name: String,
+		id: NumberOrString,
+		label: Option<String>,
+		result: Result<()>,
+	) -> Result<()> {
+		let response = self.apply_edit_response(label, result);
+		self.respond::<ApplyWorkspaceEdit>(&client_name, id, response)
+	}
+	/// Applies edit, first asking the user to confirm any change annotations that need it.
 	fn apply_workspace_edit(&mut self, edit: &WorkspaceEdit) -> Result<()> {
+		if self.queue_confirmation(edit, None, None) {
+			return Ok(());
+		}
+		self.write_workspace_edit(edit, &HashSet::new())
+	}
+	/// Adds edit to pending_edits if any of its change annotations need confirmation,
+	/// returning whether it did. request is the workspace/applyEdit to answer once the
+	/// edit is written.
+	fn queue_confirmation(
+		&mut self,
+		edit: &WorkspaceEdit,
+		label: Option<String>,
+		request: Option<(String, NumberOrString)>,
+	) -> bool {
+		let confirmations: BTreeMap<String, Option<bool>> = edit
+			.change_annotations
+			.iter()
+			.flatten()
+			.filter(|(_, annotation)| annotation.needs_confirmation.unwrap_or(false))
+			.map(|(id, _)| (id.clone(), None))
+			.collect();
+		if confirmations.is_empty() {
+			return false;
+		}
+		self.pending_edits.push(PendingEdit {
+			label,
+			edit: edit.clone(),
+			request,
+			confirmations,
+		});
+		true
+	}
+	/// Records the user's choice for an annotation of a pending edit. Once every annotation
+	/// is decided the edit is written without the rejected changes, which still counts as
+	/// applied.
+	fn confirm_edit(&mut self, idx: usize, id: &str, accept: bool) -> Result<()> {
+		let pending = &mut self.pending_edits[idx];
+		pending.confirmations.insert(id.to_string(), Some(accept));
+		if pending.confirmations.values().any(|c| c.is_none()) {
+			return Ok(());
+		}
+		let pending = self.pending_edits.remove(idx);
+		let rejected: HashSet<String> = pending
+			.confirmations
+			.into_iter()
+			.filter(|(_, accepted)| *accepted == Some(false))
+			.map(|(id, _)| id)
+			.collect();
+		let result = self.write_workspace_edit(&pending.edit, &rejected);
+		match pending.request {
+			Some((client_name, id)) => {
+				self.edit_written(client_name, id, pending.label, &pending.edit, result)
+			}
+			None => result,
+		}
+	}
+	/// Applies edit, skipping changes with a rejected annotation.
+	fn write_workspace_edit(
+		&mut self,
+		edit: &WorkspaceEdit,
+		rejected: &HashSet<String>,
+	) -> Result<()> {
+		// Lines describing each file touched, and whether any of them was not an acme window.
+		let mut summary = vec![];
+		let mut unopened = false;
 		if let Some(ref doc_changes) = edit.document_changes {
 			match doc_changes {
 				DocumentChanges::Edits(edits) => {
 					for edit in edits {
-						let text_edits = edit
-							.edits
-							.iter()
-							.filter_map(|e| {
-								match e {
-									// A TextEdit, keep it.
-									OneOf::Left(e) => Some(e),
-									// A AnnotatedTextEdit, discard until we support it.
-									_ => None,
+						let n = self.apply_document_edit(edit, rejected)?;
+						if n > 0 {
+							let (line, opened) = self.edit_summary(&edit.text_document.uri, n);
+							summary.push(line);
+							unopened |= !opened;
+						}
+					}
+				}
+				DocumentChanges::Operations(ops) => {
+					for op in ops {
+						match op {
+							DocumentChangeOperation::Edit(edit) => {
+								let n = self.apply_document_edit(edit, rejected)?;
+								if n > 0 {
+									let (line, opened) =
+										self.edit_summary(&edit.text_document.uri, n);
+									summary.push(line);
+									unopened |= !opened;
 								}
-							})
-							.cloned()
-							.collect();
-						self.apply_text_edits(
-							&edit.text_document.uri,
-							InsertTextFormat::PLAIN_TEXT,
-							&text_edits,
-						)?;
+							}
+							DocumentChangeOperation::Op(op) => {
+								let annotation = match op {
+									ResourceOp::Create(op) => op.annotation_id.as_ref(),
+									ResourceOp::Rename(op) => op.annotation_id.as_ref(),
+									ResourceOp::Delete(op) => {
+										op.options.as_ref().and_then(|o| o.annotation_id.as_ref())
+									}
+								};
+								if annotation.is_some_and(|id| rejected.contains(id)) {
+									continue;
+								}
+								self.apply_resource_op(op)?;
+								summary.push(match op {
+									ResourceOp::Create(op) => format!("created {}", op.uri.path()),
+									ResourceOp::Rename(op) => format!(
+										"renamed {} to {}",
+										op.old_uri.path(),
+										op.new_uri.path()
+									),
+									ResourceOp::Delete(op) => format!("deleted {}", op.uri.path()),
+								});
+								unopened = true;
+							}
+						}
 					}
 				}
-				_ => panic!("unsupported document_changes {:?}", doc_changes),
 			}
 		}
 		if let Some(ref changes) = edit.changes {
 			for (url, edits) in changes {
 				self.apply_text_edits(&url, InsertTextFormat::PLAIN_TEXT, &edits)?;
+				let (line, opened) = self.edit_summary(url, edits.len());
+				summary.push(line);
+				unopened |= !opened;
+			}
+		}
+		// Edits to the current window speak for themselves, so only report ones that
+		// aren't all visible.
+		if summary.len() > 1 || unopened {
+			summary.sort();
+			self.output = summary.join("\n");
+		}
+		Ok(())
+	}
+	/// Returns a summary line of n edits made to url and whether they went to an acme window.
+	fn edit_summary(&mut self, url: &Url, n: usize) -> (String, bool) {
+		let opened = self.get_sw_by_url(url).is_some();
+		let line = format!(
+			"{}: {} edit{}{}",
+			url.path(),
+			n,
+			if n == 1 { "" } else { "s" },
+			if opened {
+				""
+			} else if self.plumbed_edits.contains_key(url) {
+				" (opening in acme)"
+			} else {
+				" (written to disk)"
+			},
+		);
+		(line, opened)
+	}
+	/// Applies the edits of a document, skipping ones with a rejected annotation. Returns the
+	/// number of edits applied.
+	fn apply_document_edit(
+		&mut self,
+		edit: &TextDocumentEdit,
+		rejected: &HashSet<String>,
+	) -> Result<usize> {
+		let text_edits: Vec<TextEdit> = edit
+			.edits
+			.iter()
+			.filter_map(|e| match e {
+				OneOf::Left(e) => Some(e),
+				OneOf::Right(e) if !rejected.contains(&e.annotation_id) => Some(&e.text_edit),
+				OneOf::Right(_) => None,
+			})
+			.cloned()
+			.collect();
+		if text_edits.is_empty() {
+			return Ok(0);
+		}
+		self.apply_text_edits(
+			&edit.text_document.uri,
+			InsertTextFormat::PLAIN_TEXT,
+			&text_edits,
+		)?;
+		Ok(text_edits.len())
+	}
+	/// Creates, renames, or deletes a file, keeping any acme windows of the file in step.
+	fn apply_resource_op(&mut self, op: &ResourceOp) -> Result<()> {
+		match op {
+			ResourceOp::Create(op) => {
+				let path = op.uri.path();
+				let overwrite = op.options.as_ref().and_then(|o| o.overwrite);
+				let ignore = op.options.as_ref().and_then(|o| o.ignore_if_exists);
+				let exists = metadata(path).is_ok();
+				if exists {
+					// overwrite wins over ignore_if_exists.
+					if !overwrite.unwrap_or(false) {
+						if ignore.unwrap_or(false) {
+							return Ok(());
+						}
+						bail!("cannot create {}: file exists", path);
+					}
+				}
+				if let Some(dir) = std::path::Path::new(path).parent() {
+					std::fs::create_dir_all(dir)?;
+				}
+				std::fs::write(path, "")?;
+				if exists && self.reload_windows(path)? {
+					self.sync_windows()?;
+				}
+			}
+			ResourceOp::Rename(op) => {
+				let old_path = op.old_uri.path();
+				let new_path = op.new_uri.path();
+				let overwrite = op.options.as_ref().and_then(|o| o.overwrite);
+				let ignore = op.options.as_ref().and_then(|o| o.ignore_if_exists);
+				let replaced = metadata(new_path).is_ok();
+				if replaced && !overwrite.unwrap_or(false) {
+					if ignore.unwrap_or(false) {
+						return Ok(());
+					}
+					bail!("cannot rename {} to {}: file exists", old_path, new_path);
+				}
+				if let Some(dir) = std::path::Path::new(new_path).parent() {
+					std::fs::create_dir_all(dir)?;
+				}
+				std::fs::rename(old_path, new_path)?;
+				let reloaded = replaced && self.reload_windows(new_path)?;
+				let renamed = match self.ws.get_mut(old_path) {
+					Some(ids) => {
+						for sw in ids.values_mut() {
+							sw.w.name(new_path)?;
+						}
+						true
+					}
+					None => false,
+				};
+				if reloaded || renamed {
+					// Let sync_windows close the old document and open the new one.
+					self.sync_windows()?;
+				}
+			}
+			ResourceOp::Delete(op) => {
+				let path = op.uri.path();
+				let recursive = op.options.as_ref().and_then(|o| o.recursive);
+				let ignore = op.options.as_ref().and_then(|o| o.ignore_if_not_exists);
+				match metadata(path) {
+					Ok(md) if md.is_dir() => {
+						if recursive.unwrap_or(false) {
+							std::fs::remove_dir_all(path)?;
+						} else {
+							std::fs::remove_dir(path)?;
+						}
+					}
+					Ok(_) => std::fs::remove_file(path)?,
+					Err(_) if ignore.unwrap_or(false) => return Ok(()),
+					Err(err) => bail!("cannot delete {}: {}", path, err),
+				}
+				// Mark the windows of deleted files dirty so their contents aren't lost
+				// without a warning.
+				let dir = format!("{}/", path.trim_end_matches('/'));
+				for (name, ids) in self.ws.iter_mut() {
+					if name != path && !name.starts_with(&dir) {
+						continue;
+					}
+					for sw in ids.values_mut() {
+						sw.w.ctl("dirty")?;
+						sw.w.write(File::Tag, " (deleted)")?;
+					}
+				}
 			}
 		}
 		Ok(())
 	}
+	/// Reloads the windows of path from disk after the file was replaced, so they can't Put the
+	/// old contents back, and closes its document. sync_windows then reopens the document with
+	/// the new contents. Returns whether path had any windows.
+	fn reload_windows(&mut self, path: &str) -> Result<bool> {
+		let ids = match self.ws.remove(path) {
+			Some(ids) => ids,
+			None => return Ok(false),
+		};
+		let mut url_client = None;
+		for (_, mut sw) in ids {
+			sw.w.ctl("get")?;
+			url_client = Some((sw.url.clone(), sw.client.clone()));
+		}
+		if let Some((url, client_name)) = url_client {
+			self.send_notification::<DidCloseTextDocument>(
+				&client_name,
+				DidCloseTextDocumentParams {
+					text_document: TextDocumentIdentifier::new(url),
+				},
+			)?;
+		}
+		Ok(true)
+	}
 	fn apply_text_edits(
 		&mut self,
 		url: &Url,
@@ -1108,13 +2697,33 @@ impl Server {
 		if edits.is_empty() {
 			return Ok(());
 		}
+		let encoding = self.url_encoding(url);
+		if self.get_sw_by_url(url).is_none() {
+			if let Some(pending) = self.plumbed_edits.get_mut(url) {
+				pending.edits.push((format, edits.clone()));
+				return Ok(());
+			}
+			if self.plumb_file(url.path())? {
+				self.plumbed_edits.insert(
+					url.clone(),
+					PlumbedEdits {
+						since: Instant::now(),
+						edits: vec![(format, edits.clone())],
+					},
+				);
+				return Ok(());
+			}
+			return apply_file_edits(url.path(), edits, encoding);
+		}
 		let (_id, sw) = match self.get_sw_by_url(url) {
 			Some(v) => v,
-			None => return Ok(()),
+			None => return apply_file_edits(url.path(), edits, encoding),
 		};
+		let mut edits = edits.clone();
+		edits.sort_by(|a, b| cmp_range(&a.range, &b.range));
 		let mut body = String::new();
 		sw.w.read(File::Body)?.read_to_string(&mut body)?;
-		let offsets = NlOffsets::new(std::io::Cursor::new(body.clone()))?;
+		let offsets = NlOffsets::with_encoding(std::io::Cursor::new(body.clone()), sw.encoding)?;
 		if edits.len() == 1 {
 			if body == edits[0].new_text {
 				return Ok(());
@@ -1148,52 +2757,118 @@ impl Server {
 		sw.w.seek(File::Body, std::io::SeekFrom::Start(0))?;
 		sw.w.ctl("nomark")?;
 		sw.w.ctl("mark")?;
-		for edit in edits.iter().rev() {
+		// Tabstops of snippet edits, as rune offsets into the edited body.
+		let mut tabstops = vec![];
+		// Runes added by the edits before the current one.
+		let mut delta: i64 = 0;
+		let mut texts = vec![];
+		for edit in &edits {
 			let soff = offsets.line_to_offset(edit.range.start.line, edit.range.start.character);
 			let eoff = offsets.line_to_offset(edit.range.end.line, edit.range.end.character);
-			let addr = format!("#{},#{}", soff, eoff);
-			sw.w.addr(&addr)?;
-			match format {
-				InsertTextFormat::SNIPPET => {
-					lazy_static! {
-						static ref SNIPPET: Regex =
-							Regex::new(r"(\$\{\d+:[[:alpha:]]+\})|(\$0)").unwrap();
+			let text =
+				match format {
+					InsertTextFormat::SNIPPET => {
+						let exp = snippet::expand(&edit.new_text);
+						let start = soff as i64 + delta;
+						tabstops.extend(exp.tabstops.iter().map(|(s, e)| {
+							((start + *s as i64) as u32, (start + *e as i64) as u32)
+						}));
+						exp.text
 					}
-					let text = &SNIPPET.replace_all(&edit.new_text, "");
-					sw.w.write(File::Data, text)?;
-					text.len()
-				}
-				InsertTextFormat::PLAIN_TEXT => {
-					sw.w.write(File::Data, &edit.new_text)?;
-					edit.new_text.len()
-				}
-				_ => panic!("unexpected {:?}", format),
-			};
+					InsertTextFormat::PLAIN_TEXT => edit.new_text.clone(),
+					_ => panic!("unexpected {:?}", format),
+				};
+			delta += text.chars().count() as i64 - (eoff - soff) as i64;
+			texts.push((soff, eoff, text));
+		}
+		for (soff, eoff, text) in texts.iter().rev() {
+			sw.w.addr(&format!("#{},#{}", soff, eoff))?;
+			sw.w.write(File::Data, text)?;
+		}
+		if !tabstops.is_empty() {
+			self.snippet = Some(Snippet {
+				url: url.clone(),
+				tabstops,
+				next: 0,
+				len: (body.chars().count() as i64 + delta) as u32,
+			});
+			self.next_tabstop()?;
+		}
+		Ok(())
+	}
+	/// Selects the next tabstop of the last inserted snippet.
+	fn next_tabstop(&mut self) -> Result<()> {
+		let mut snippet = match self.snippet.take() {
+			Some(s) => s,
+			None => return Ok(()),
+		};
+		let (_, sw) = match self.get_sw_by_url(&snippet.url) {
+			Some(v) => v,
+			None => return Ok(()),
+		};
+		let mut body = String::new();
+		sw.w.read(File::Body)?.read_to_string(&mut body)?;
+		let len = body.chars().count() as u32;
+		// Text typed into the previous tabstop moves the ones after it.
+		let delta = len as i64 - snippet.len as i64;
+		for (s, e) in snippet.tabstops[snippet.next..].iter_mut() {
+			*s = (*s as i64 + delta).clamp(0, len as i64) as u32;
+			*e = (*e as i64 + delta).clamp(0, len as i64) as u32;
+		}
+		let (s, e) = snippet.tabstops[snippet.next];
+		sw.w.addr(&format!("#{},#{}", s, e))?;
+		sw.w.ctl("dot=addr")?;
+		sw.w.ctl("show")?;
+		snippet.next += 1;
+		snippet.len = len;
+		if snippet.next < snippet.tabstops.len() {
+			self.snippet = Some(snippet);
 		}
 		Ok(())
 	}
-	fn did_change(&mut self, name: String, wid: usize) -> Result<()> {
+	/// Sends the window's changes to its server, returning whether there were any.
+	fn did_change(&mut self, name: String, wid: usize) -> Result<bool> {
 		// Sometimes we are sending a DidChange before a DidOpen. Maybe this is because
 		// acme's event log sometimes misses events. Sync the windows just to be sure.
 		self.sync_windows()?;
 		// Because zerox windows don't appear in the windows call, make sure that
 		// whatever wid we are given is initialized.
 		if self.init_win(name.clone(), wid).is_err() {
-			return Ok(());
+			return Ok(false);
+		}
+		let client = match self.get_sw_by_name_id(&name, &wid) {
+			Some(sw) => sw.client.clone(),
+			None => return Ok(false),
+		};
+		let kind = match self.capabilities.get(&client) {
+			Some(caps) => sync_kind(caps),
+			None => return Ok(false),
+		};
+		if kind == TextDocumentSyncKind::NONE {
+			return Ok(false);
 		}
 		let sw = match self.get_sw_by_name_id(&name, &wid) {
 			Some(sw) => sw,
-			None => return Ok(()),
+			None => return Ok(false),
+		};
+		let params = match sw.change_params(kind)? {
+			Some(params) => params,
+			None => return Ok(false),
 		};
-		let client = sw.client.clone();
-		let params = sw.change_params()?;
-		self.send_notification::<DidChangeTextDocument>(&client, params)
+		// Zerox'd windows share the document, so keep them in step.
+		let (version, synced) = (sw.version, sw.synced.clone());
+		for other in self.ws.get_mut(&name).unwrap().values_mut() {
+			other.version = version;
+			other.synced = synced.clone();
+		}
+		self.send_notification::<DidChangeTextDocument>(&client, params)?;
+		Ok(true)
 	}
 	fn set_focus(&mut self, ev: LogEvent) -> Result<()> {
 		self.focus = ev.name.clone();
 		self.focus_id.insert(ev.name.clone(), ev.id);
 
-		self.did_change(ev.name.clone(), ev.id)?;
+		let changed = self.did_change(ev.name.clone(), ev.id)?;
 		let sw = match self.get_sw_by_name_id(&ev.name, &ev.id) {
 			Some(sw) => sw,
 			None => return Ok(()),
@@ -1201,20 +2876,56 @@ impl Server {
 		let client_name = &sw.client.clone();
 		let url = sw.url.clone();
 		let range = sw.range()?;
+		// The character before dot, to check for on type formatting triggers.
+		let (q0, _) = sw.pos()?;
+		let typed = match q0 {
+			0 => None,
+			_ => sw.synced.chars().nth(q0 as usize - 1),
+		};
 		let text_document_position_params =
 			TextDocumentPositionParams::new(sw.doc_ident(), range.start);
 		let text_document = TextDocumentIdentifier::new(url.clone());
 		let line = sw.line()?;
+		let encoding = sw.encoding;
+		// Inlay hints are shown for the cursor lines and their neighbours.
+		let first_hint_line = range.start.line.saturating_sub(1);
+		let hint_lines: Vec<String> = sw
+			.synced
+			.lines()
+			.skip(first_hint_line as usize)
+			.take((range.end.line + 2 - first_hint_line) as usize)
+			.map(|l| l.to_string())
+			.collect();
 		drop(sw);
 
+		if changed {
+			if let Some(ch) = typed {
+				self.format_on_type(client_name, &url, range.start, ch)?;
+			}
+		}
+		// Servers that sent an incomplete list expect to be asked again as the word is typed.
+		let trigger_kind = match &self.current_hover {
+			Some(hover) if hover.url == url && hover.completion_incomplete => {
+				CompletionTriggerKind::TRIGGER_FOR_INCOMPLETE_COMPLETIONS
+			}
+			_ => CompletionTriggerKind::INVOKED,
+		};
+		self.lens_resolves.clear();
 		self.current_hover = Some(WindowHover {
 			client_name: client_name.into(),
 			url: url.clone(),
 			line,
+			range,
+			encoding,
+			first_hint_line,
+			hint_lines,
+			hints: vec![],
+			highlights: vec![],
 			token: None,
 			signature: None,
 			lens: vec![],
 			completion: vec![],
+			completion_incomplete: false,
 			code_actions: vec![],
 			actions: vec![],
 			action_addrs: vec![],
@@ -1238,6 +2949,7 @@ impl Server {
 				context: CodeActionContext {
 					diagnostics: vec![],
 					only: None,
+					trigger_kind: None,
 				},
 				work_done_progress_params,
 				partial_result_params,
@@ -1251,7 +2963,7 @@ impl Server {
 				work_done_progress_params,
 				partial_result_params,
 				context: Some(CompletionContext {
-					trigger_kind: CompletionTriggerKind::INVOKED,
+					trigger_kind,
 					trigger_character: None,
 				}),
 			},
@@ -1275,15 +2987,128 @@ impl Server {
 				work_done_progress_params,
 			},
 		)?;
-		self.send_request::<CodeLensRequest>(
-			client_name,
-			url.clone(),
-			CodeLensParams {
-				text_document,
-				work_done_progress_params,
-				partial_result_params,
-			},
-		)?;
+		if self
+			.capabilities
+			.get(client_name)
+			.and_then(|caps| caps.document_highlight_provider.as_ref())
+			.is_some()
+		{
+			self.send_request::<DocumentHighlightRequest>(
+				client_name,
+				url.clone(),
+				DocumentHighlightParams {
+					text_document_position_params: text_document_position_params.clone(),
+					work_done_progress_params,
+					partial_result_params,
+				},
+			)?;
+		}
+		if self
+			.capabilities
+			.get(client_name)
+			.and_then(|caps| caps.inlay_hint_provider.as_ref())
+			.is_some()
+		{
+			self.send_request::<InlayHintRequest>(
+				client_name,
+				url.clone(),
+				InlayHintParams {
+					text_document: text_document.clone(),
+					range: Range::new(
+						Position::new(first_hint_line, 0),
+						Position::new(range.end.line + 2, 0),
+					),
+					work_done_progress_params,
+				},
+			)?;
+		}
+		self.send_request::<CodeLensRequest>(
+			client_name,
+			url.clone(),
+			CodeLensParams {
+				text_document,
+				work_done_progress_params,
+				partial_result_params,
+			},
+		)?;
+		self.pull_diagnostics(client_name, url)?;
+		Ok(())
+	}
+	/// Returns the options sent with formatting requests for path. The defaults are overridden
+	/// by the server's formatting config, then by .editorconfig, then by a project .acre.toml.
+	fn formatting_options(&self, client_name: &str, path: &str) -> FormattingOptions {
+		let mut opts = FormattingOptions {
+			tab_size: 4,
+			insert_spaces: false,
+			properties: HashMap::new(),
+			trim_trailing_whitespace: Some(true),
+			insert_final_newline: Some(true),
+			trim_final_newlines: Some(true),
+		};
+		if let Some(f) = self
+			.config
+			.servers
+			.get(client_name)
+			.and_then(|c| c.formatting.as_ref())
+		{
+			f.apply(&mut opts);
+		}
+		let path = std::path::Path::new(path);
+		ConfigFormatting::from_editorconfig(&editorconfig::properties(path)).apply(&mut opts);
+		if let Some(f) = project_formatting(path) {
+			f.apply(&mut opts);
+		}
+		opts
+	}
+	/// Sends textDocument/onTypeFormatting if it is enabled and ch is one of the server's
+	/// trigger characters.
+	fn format_on_type(
+		&mut self,
+		client_name: &str,
+		url: &Url,
+		position: Position,
+		ch: char,
+	) -> Result<()> {
+		if !self
+			.config
+			.servers
+			.get(client_name)
+			.and_then(|c| c.format_on_type)
+			.unwrap_or(false)
+		{
+			return Ok(());
+		}
+		let ch = ch.to_string();
+		let triggered = match self
+			.capabilities
+			.get(client_name)
+			.and_then(|caps| caps.document_on_type_formatting_provider.as_ref())
+		{
+			Some(opts) => {
+				opts.first_trigger_character == ch
+					|| opts
+						.more_trigger_character
+						.iter()
+						.flatten()
+						.any(|c| *c == ch)
+			}
+			None => false,
+		};
+		if !triggered {
+			return Ok(());
+		}
+		self.send_request::<OnTypeFormatting>(
+			client_name,
+			url.clone(),
+			DocumentOnTypeFormattingParams {
+				text_document_position: TextDocumentPositionParams::new(
+					TextDocumentIdentifier::new(url.clone()),
+					position,
+				),
+				ch,
+				options: self.formatting_options(client_name, url.path()),
+			},
+		)?;
 		Ok(())
 	}
 	fn run_event(&mut self, ev: Event, filename: &str) -> Result<()> {
@@ -1297,7 +3122,9 @@ impl Server {
 		let text_document_position = text_document_position_params.clone();
 		let text_document = TextDocumentIdentifier::new(url.clone());
 		drop(sw);
-		self.did_change(filename.to_string(), id)?;
+		if self.did_change(filename.to_string(), id)? {
+			self.requery_completion(&url)?;
+		}
 		match ev.text.as_str() {
 			"definition" => {
 				self.send_request::<GotoDefinition>(
@@ -1335,6 +3162,136 @@ impl Server {
 					},
 				)?;
 			}
+			"wsymbols" => {
+				let query = if ev.arg.trim().is_empty() {
+					self.selection()?
+				} else {
+					ev.arg.clone()
+				};
+				self.send_request::<WorkspaceSymbolRequest>(
+					client_name,
+					url,
+					WorkspaceSymbolParams {
+						query: query.trim().to_string(),
+						work_done_progress_params,
+						partial_result_params,
+					},
+				)?;
+			}
+			"callers" | "callees" | "supertypes" | "subtypes" => {
+				let kind = match ev.text.as_str() {
+					"callers" => HierarchyKind::Callers,
+					"callees" => HierarchyKind::Callees,
+					"supertypes" => HierarchyKind::Supertypes,
+					_ => HierarchyKind::Subtypes,
+				};
+				self.hierarchy = Some(Hierarchy {
+					client_name: client_name.to_string(),
+					kind,
+					nodes: vec![],
+				});
+				self.hierarchy_expands.clear();
+				match kind {
+					HierarchyKind::Callers | HierarchyKind::Callees => {
+						self.send_request::<CallHierarchyPrepare>(
+							client_name,
+							url,
+							CallHierarchyPrepareParams {
+								text_document_position_params,
+								work_done_progress_params,
+							},
+						)?;
+					}
+					HierarchyKind::Supertypes | HierarchyKind::Subtypes => {
+						self.send_request::<TypeHierarchyPrepare>(
+							client_name,
+							url,
+							TypeHierarchyPrepareParams {
+								text_document_position_params,
+								work_done_progress_params,
+							},
+						)?;
+					}
+				}
+			}
+			"expand" | "shrink" => {
+				let msg_id = self.send_request::<SelectionRangeRequest>(
+					client_name,
+					url,
+					SelectionRangeParams {
+						text_document,
+						positions: vec![text_document_position_params.position],
+						work_done_progress_params,
+						partial_result_params,
+					},
+				)?;
+				let dot = match self.get_sw_by_name(filename) {
+					Some((_, sw)) => sw.range()?,
+					None => return Ok(()),
+				};
+				self.selection_ranges.insert(
+					ClientId::new(client_name.as_str(), msg_id),
+					(ev.text == "expand", dot),
+				);
+			}
+			"outline" | "region" => {
+				let dot = if ev.text == "region" {
+					match self.get_sw_by_name(filename) {
+						Some((_, sw)) => Some(sw.range()?),
+						None => return Ok(()),
+					}
+				} else {
+					None
+				};
+				let msg_id = self.send_request::<FoldingRangeRequest>(
+					client_name,
+					url,
+					FoldingRangeParams {
+						text_document,
+						work_done_progress_params,
+						partial_result_params,
+					},
+				)?;
+				self.folding_ranges
+					.insert(ClientId::new(client_name.as_str(), msg_id), dot);
+			}
+			"format" => {
+				let range = match self.get_sw_by_name(filename) {
+					Some((_, sw)) => sw.range()?,
+					None => return Ok(()),
+				};
+				let options = self.formatting_options(client_name, url.path());
+				self.send_request::<RangeFormatting>(
+					client_name,
+					url,
+					DocumentRangeFormattingParams {
+						text_document,
+						range,
+						options,
+						work_done_progress_params,
+					},
+				)?;
+			}
+			"tabstop" => {
+				self.next_tabstop()?;
+			}
+			"highlight" => {
+				let highlights = match &self.current_hover {
+					Some(hover) if hover.url == url => hover.highlights.clone(),
+					_ => vec![],
+				};
+				self.select_next_highlight(filename, &highlights)?;
+			}
+			"runnables" => {
+				self.send_request::<lsp::Runnables>(
+					client_name,
+					url,
+					lsp::RunnablesParams {
+						text_document,
+						position: Some(text_document_position_params.position),
+					},
+				)?;
+			}
 			"impl" => {
 				self.send_request::<GotoImplementation>(
 					client_name,
@@ -1357,6 +3314,43 @@ impl Server {
 					},
 				)?;
 			}
+			"rename" => {
+				let new_name = if ev.arg.trim().is_empty() {
+					self.selection()?
+				} else {
+					ev.arg.clone()
+				};
+				let new_name = new_name.trim().to_string();
+				if new_name.is_empty() || new_name.contains(char::is_whitespace) {
+					self.output =
+						"rename: select the new name in this window or chord it with [rename]"
+							.to_string();
+					return Ok(());
+				}
+				let params = RenameParams {
+					text_document_position: text_document_position_params.clone(),
+					new_name,
+					work_done_progress_params,
+				};
+				let prepare = match self.capabilities.get(client_name.as_str()) {
+					Some(ServerCapabilities {
+						rename_provider: Some(OneOf::Right(opts)),
+						..
+					}) => opts.prepare_provider.unwrap_or(false),
+					_ => false,
+				};
+				if prepare {
+					let msg_id = self.send_request::<PrepareRenameRequest>(
+						client_name,
+						url,
+						text_document_position_params,
+					)?;
+					self.renames
+						.insert(ClientId::new(client_name.as_str(), msg_id), params);
+				} else {
+					self.send_request::<Rename>(client_name, url, params)?;
+				}
+			}
 			_ => {}
 		}
 		Ok(())
@@ -1384,55 +3378,163 @@ impl Server {
 	fn run_action(&mut self, client_name: &str, url: Url, action: Action) -> Result<()> {
 		match action {
 			Action::Command(CodeActionOrCommand::Command(cmd)) => {
-				if let Some(args) = cmd.arguments {
-					for arg in args {
-						#[derive(Deserialize)]
-						#[serde(rename_all = "camelCase")]
-						struct ArgWorkspaceEdit {
-							workspace_edit: WorkspaceEdit,
-						}
-						match serde_json::from_value::<ArgWorkspaceEdit>(arg) {
-							Ok(v) => self.apply_workspace_edit(&v.workspace_edit)?,
-							Err(err) => {
-								eprintln!("json err {}", err);
-								continue;
-							}
-						}
-					}
-				}
+				self.execute_command(client_name, url, cmd)?;
 			}
 			Action::Command(CodeActionOrCommand::CodeAction(action)) => {
-				if let Some(edit) = action.edit {
-					self.apply_workspace_edit(&edit)?;
-				} else {
+				if action.edit.is_none() && action.command.is_none() {
 					let _id = self.send_request::<CodeActionResolveRequest>(
 						client_name.into(),
 						url,
 						action,
 					)?;
+				} else {
+					// The edit is applied before the command is run.
+					if let Some(edit) = action.edit {
+						self.apply_workspace_edit(&edit)?;
+					}
+					if let Some(cmd) = action.command {
+						self.execute_command(client_name, url, cmd)?;
+					}
 				}
 			}
 			Action::Completion(item) => {
-				let format = item
-					.insert_text_format
-					.unwrap_or(InsertTextFormat::PLAIN_TEXT);
-				if let Some(edit) = item.text_edit.clone() {
-					match edit {
-						CompletionTextEdit::Edit(edit) => {
-							return self.apply_text_edits(&url, format, &vec![edit])
-						}
-						CompletionTextEdit::InsertAndReplace(_) => {
-							eprintln!("InsertAndReplace not supported");
-							return Ok(());
-						}
-					}
+				let resolve = match self.capabilities.get(client_name) {
+					Some(ServerCapabilities {
+						completion_provider: Some(opts),
+						..
+					}) => opts.resolve_provider.unwrap_or(false),
+					_ => false,
+				};
+				// Servers may leave out the edits (like auto imports) until the item is resolved.
+				if resolve && item.additional_text_edits.is_none() {
+					self.send_request::<ResolveCompletionItem>(client_name, url, item)?;
+				} else {
+					self.apply_completion(client_name, &url, item)?;
 				}
-				panic!("unsupported");
 			}
 			Action::CodeLens(lens) => {
-				// TODO: rust-analyzer complains about "code lens without data" here. If I set
-				// lens.data to Value::Null, it complains with some resolution error.
-				let _id = self.send_request::<CodeLensResolve>(client_name.into(), url, lens)?;
+				if let Some(cmd) = lens.command {
+					self.execute_command(client_name, url, cmd)?;
+				}
+			}
+		}
+		Ok(())
+	}
+	/// Inserts a completion item along with its additional edits.
+	fn apply_completion(
+		&mut self,
+		client_name: &str,
+		url: &Url,
+		item: CompletionItem,
+	) -> Result<()> {
+		let format = item
+			.insert_text_format
+			.unwrap_or(InsertTextFormat::PLAIN_TEXT);
+		let edit = match item.text_edit {
+			Some(CompletionTextEdit::Edit(edit)) => edit,
+			Some(CompletionTextEdit::InsertAndReplace(edit)) => {
+				let mode = self
+					.config
+					.servers
+					.get(client_name)
+					.and_then(|c| c.completion_edit)
+					.unwrap_or(CompletionEdit::Replace);
+				match mode {
+					CompletionEdit::Insert => TextEdit::new(edit.insert, edit.new_text),
+					CompletionEdit::Replace => TextEdit::new(edit.replace, edit.new_text),
+				}
+			}
+			// Without an edit, the text replaces the word before the cursor.
+			None => {
+				let (_, sw) = match self.get_sw_by_url(url) {
+					Some(v) => v,
+					None => return Ok(()),
+				};
+				let (q0, q1) = sw.pos()?;
+				let mut body = String::new();
+				sw.w.read(File::Body)?.read_to_string(&mut body)?;
+				let word = body
+					.chars()
+					.take(q0 as usize)
+					.collect::<Vec<char>>()
+					.into_iter()
+					.rev()
+					.take_while(|c| c.is_alphanumeric() || *c == '_')
+					.count() as u32;
+				let nl = NlOffsets::with_encoding(body.as_bytes(), sw.encoding)?;
+				let (sl, sc) = nl.offset_to_line(q0 - word);
+				let (el, ec) = nl.offset_to_line(q1);
+				TextEdit::new(
+					Range::new(Position::new(sl, sc), Position::new(el, ec)),
+					item.insert_text.unwrap_or(item.label),
+				)
+			}
+		};
+		let mut edits = vec![edit];
+		for edit in item.additional_text_edits.unwrap_or_default() {
+			// The edits are applied together, so plain text must survive snippet expansion.
+			let new_text = match format {
+				InsertTextFormat::SNIPPET => snippet::escape(&edit.new_text),
+				_ => edit.new_text,
+			};
+			edits.push(TextEdit::new(edit.range, new_text));
+		}
+		self.apply_text_edits(url, format, &edits)
+	}
+	/// Runs cmd on the server if it advertises it. Otherwise the command is expected to be
+	/// handled by the client, which only supports arguments carrying a workspace edit.
+	fn execute_command(&mut self, client_name: &str, url: Url, cmd: Command) -> Result<()> {
+		let supported = self
+			.capabilities
+			.get(client_name)
+			.and_then(|caps| caps.execute_command_provider.as_ref())
+			.map(|p| p.commands.contains(&cmd.command))
+			.unwrap_or(false);
+		if supported {
+			self.send_request::<ExecuteCommand>(
+				client_name,
+				url,
+				ExecuteCommandParams {
+					command: cmd.command,
+					arguments: cmd.arguments.unwrap_or_default(),
+					work_done_progress_params,
+				},
+			)?;
+			return Ok(());
+		}
+		// Run and Debug lenses carry the runnable as their only argument. There is no debugger,
+		// so both just run it.
+		if cmd.command == "rust-analyzer.runSingle" || cmd.command == "rust-analyzer.debugSingle" {
+			if let Some(runnable) = cmd.arguments.as_ref().and_then(|args| args.first()) {
+				let runnable = serde_json::from_value::<lsp::Runnable>(runnable.clone())?;
+				run_runnable(&runnable)?;
+			}
+			return Ok(());
+		}
+		// Reference lenses ask the client to show locations: (uri, position, locations).
+		if cmd.command == "rust-analyzer.showReferences"
+			|| cmd.command == "editor.action.showReferences"
+		{
+			if let Some(locs) = cmd.arguments.as_ref().and_then(|args| args.get(2)) {
+				let locs = serde_json::from_value::<Vec<Location>>(locs.clone())?;
+				self.locations_output(locs);
+			}
+			return Ok(());
+		}
+		if let Some(args) = cmd.arguments {
+			for arg in args {
+				#[derive(Deserialize)]
+				#[serde(rename_all = "camelCase")]
+				struct ArgWorkspaceEdit {
+					workspace_edit: WorkspaceEdit,
+				}
+				match serde_json::from_value::<ArgWorkspaceEdit>(arg) {
+					Ok(v) => self.apply_workspace_edit(&v.workspace_edit)?,
+					Err(err) => {
+						eprintln!("json err {}", err);
+						continue;
+					}
+				}
 			}
 		}
 		Ok(())
@@ -1445,24 +3547,67 @@ impl Server {
 					self.output.clear();
 					self.sync_windows()?;
 					self.diags.clear();
+					self.diag_result_ids.clear();
 					self.current_hover = None;
+					self.hierarchy = None;
+					self.hierarchy_expands.clear();
+					self.runnables.clear();
+				}
+				"Diagnostics" => {
+					self.open_diagnostics()?;
 				}
 				_ => {
-					panic!("unexpected");
+					if let Some(name) = self.addr_name(ev.q0) {
+						let mut ev = ev;
+						ev.text = ev.text.trim_matches(|c| c == '[' || c == ']').to_string();
+						return self.run_event(ev, &name);
+					}
 				}
 			},
 			'L' => {
+				if let Some(name) = self.addr_name(ev.q0) {
+					return self.run_event(ev, &name);
+				}
+				if let Some(&(_, _, req_idx, action_idx)) = self
+					.message_addrs
+					.iter()
+					.find(|(start, end, _, _)| (*start as u32) <= ev.q0 && ev.q0 < *end as u32)
 				{
-					let mut name = None;
-					for (pos, n) in self.addr.iter().rev() {
-						if (*pos as u32) < ev.q0 {
-							name = n.clone();
-							break;
-						}
-					}
-					if let Some(name) = name {
-						return self.run_event(ev, &name);
-					}
+					let req = self.message_requests.remove(req_idx);
+					let action = req
+						.params
+						.actions
+						.and_then(|actions| actions.into_iter().nth(action_idx));
+					return self.respond::<ShowMessageRequest>(&req.client_name, req.id, action);
+				}
+				if let Some((_, _, idx, id, accept)) = self
+					.pending_edit_addrs
+					.iter()
+					.find(|(start, end, _, _, _)| (*start as u32) <= ev.q0 && ev.q0 < *end as u32)
+					.cloned()
+				{
+					return self.confirm_edit(idx, &id, accept);
+				}
+				if let Some(&(_, _, idx)) = self
+					.symbol_addrs
+					.iter()
+					.find(|(start, end, _)| (*start as u32) <= ev.q0 && ev.q0 < *end as u32)
+				{
+					return self.resolve_symbol(idx);
+				}
+				if let Some(&(_, _, idx)) = self
+					.runnable_addrs
+					.iter()
+					.find(|(start, end, _)| (*start as u32) <= ev.q0 && ev.q0 < *end as u32)
+				{
+					return run_runnable(&self.runnables[idx].1);
+				}
+				if let Some(&(_, _, idx)) = self
+					.hierarchy_addrs
+					.iter()
+					.find(|(start, end, _)| (*start as u32) <= ev.q0 && ev.q0 < *end as u32)
+				{
+					return self.toggle_hierarchy(idx);
 				}
 				{
 					let mut action: Option<(String, Url, Action)> = None;
@@ -1496,8 +3641,357 @@ impl Server {
 		}
 		Ok(())
 	}
+	/// Sends path to the plumber if configured to and a server handles it, returning whether
+	/// it did. The window opens asynchronously, and sync_windows applies the file's
+	/// plumbed_edits once it does.
+	fn plumb_file(&mut self, path: &str) -> Result<bool> {
+		if !self.config.plumb_edited_files.unwrap_or(false)
+			|| metadata(path).is_err()
+			|| self.lookup_client(path.to_string()).is_err()
+		{
+			return Ok(false);
+		}
+		plumb_location(path.to_string())?;
+		Ok(true)
+	}
+	/// Returns the filename whose command row contains the acre window position q0.
+	fn addr_name(&self, q0: u32) -> Option<String> {
+		for (pos, n) in self.addr.iter().rev() {
+			if (*pos as u32) < q0 {
+				return n.clone();
+			}
+		}
+		None
+	}
+	/// Requests the next level of a hierarchy node.
+	fn expand_hierarchy(&mut self, idx: usize) -> Result<()> {
+		let tree = match self.hierarchy.as_ref() {
+			Some(tree) if idx < tree.nodes.len() => tree,
+			_ => return Ok(()),
+		};
+		let client_name = tree.client_name.clone();
+		let url = tree.nodes[idx].item.location().uri;
+		let msg_id = match (tree.kind, &tree.nodes[idx].item) {
+			(HierarchyKind::Callers, HierarchyItem::Call(item)) => {
+				let item = item.clone();
+				self.send_request::<CallHierarchyIncomingCalls>(
+					&client_name,
+					url,
+					CallHierarchyIncomingCallsParams {
+						item,
+						work_done_progress_params,
+						partial_result_params,
+					},
+				)?
+			}
+			(HierarchyKind::Callees, HierarchyItem::Call(item)) => {
+				let item = item.clone();
+				self.send_request::<CallHierarchyOutgoingCalls>(
+					&client_name,
+					url,
+					CallHierarchyOutgoingCallsParams {
+						item,
+						work_done_progress_params,
+						partial_result_params,
+					},
+				)?
+			}
+			(HierarchyKind::Supertypes, HierarchyItem::Type(item)) => {
+				let item = item.clone();
+				self.send_request::<TypeHierarchySupertypes>(
+					&client_name,
+					url,
+					TypeHierarchySupertypesParams {
+						item,
+						work_done_progress_params,
+						partial_result_params,
+					},
+				)?
+			}
+			(HierarchyKind::Subtypes, HierarchyItem::Type(item)) => {
+				let item = item.clone();
+				self.send_request::<TypeHierarchySubtypes>(
+					&client_name,
+					url,
+					TypeHierarchySubtypesParams {
+						item,
+						work_done_progress_params,
+						partial_result_params,
+					},
+				)?
+			}
+			// A stale node from a hierarchy of another kind.
+			_ => return Ok(()),
+		};
+		if let Some(tree) = self.hierarchy.as_mut() {
+			tree.nodes[idx].expanded = true;
+		}
+		self.hierarchy_expands
+			.insert(ClientId::new(&client_name, msg_id), idx);
+		Ok(())
+	}
+	/// Expands a collapsed hierarchy node one more level, or collapses an expanded one.
+	fn toggle_hierarchy(&mut self, idx: usize) -> Result<()> {
+		let tree = match self.hierarchy.as_mut() {
+			Some(tree) => tree,
+			None => return Ok(()),
+		};
+		if !tree.nodes[idx].expanded {
+			return self.expand_hierarchy(idx);
+		}
+		tree.nodes[idx].expanded = false;
+		let depth = tree.nodes[idx].depth;
+		let end = tree.nodes[idx + 1..]
+			.iter()
+			.position(|node| node.depth <= depth)
+			.map_or(tree.nodes.len(), |n| idx + 1 + n);
+		tree.nodes.drain(idx + 1..end);
+		let removed = end - idx - 1;
+		// Forget pending expansions of this node and the removed ones, and shift those after them.
+		self.hierarchy_expands.retain(|_, i| *i < idx || *i >= end);
+		for i in self.hierarchy_expands.values_mut() {
+			if *i >= end {
+				*i -= removed;
+			}
+		}
+		Ok(())
+	}
+	/// Sets the items a hierarchy starts from and expands each of them.
+	fn set_hierarchy_roots(&mut self, items: Vec<HierarchyItem>) -> Result<()> {
+		let tree = match self.hierarchy.as_mut() {
+			Some(tree) => tree,
+			None => return Ok(()),
+		};
+		tree.nodes = items
+			.into_iter()
+			.map(|item| HierarchyNode {
+				item,
+				depth: 0,
+				expanded: false,
+			})
+			.collect();
+		if tree.nodes.is_empty() {
+			self.output = format!("no {} here", tree.kind.name());
+			self.hierarchy = None;
+			return Ok(());
+		}
+		for idx in 0..tree.nodes.len() {
+			self.expand_hierarchy(idx)?;
+		}
+		Ok(())
+	}
+	/// Inserts the result of a hierarchy expansion below its node.
+	fn set_hierarchy_children(&mut self, client_id: &ClientId, mut items: Vec<HierarchyItem>) {
+		let (tree, idx) = match (
+			self.hierarchy.as_mut(),
+			self.hierarchy_expands.remove(client_id),
+		) {
+			(Some(tree), Some(idx)) => (tree, idx),
+			_ => return,
+		};
+		items.sort_by(|a, b| cmp_location(&a.location(), &b.location()));
+		let depth = tree.nodes[idx].depth + 1;
+		let n = items.len();
+		tree.nodes.splice(
+			idx + 1..idx + 1,
+			items.into_iter().map(|item| HierarchyNode {
+				item,
+				depth,
+				expanded: false,
+			}),
+		);
+		for i in self.hierarchy_expands.values_mut() {
+			if *i > idx {
+				*i += n;
+			}
+		}
+	}
+	/// Selects the first highlight after dot in the file's window, wrapping around to the first.
+	fn select_next_highlight(
+		&mut self,
+		filename: &str,
+		highlights: &[DocumentHighlight],
+	) -> Result<()> {
+		let (_, sw) = match self.get_sw_by_name(filename) {
+			Some(v) => v,
+			None => return Ok(()),
+		};
+		if highlights.is_empty() {
+			return Ok(());
+		}
+		let dot = sw.range()?;
+		let next = highlights
+			.iter()
+			.find(|h| cmp_position(&h.range.start, &dot.start) == Ordering::Greater)
+			.unwrap_or(&highlights[0]);
+		sw.select(&next.range)
+	}
+	/// Plumbs the location of a definition response if there is only one, otherwise lists them.
+	fn goto_definition(&mut self, goto: GotoDefinitionResponse) -> Result<()> {
+		let locs: Vec<Location> = match goto {
+			GotoDefinitionResponse::Scalar(loc) => vec![loc],
+			GotoDefinitionResponse::Array(locs) => locs,
+			GotoDefinitionResponse::Link(links) => links
+				.into_iter()
+				.map(|link| Location::new(link.target_uri, link.target_selection_range))
+				.collect(),
+		};
+		match locs.len() {
+			0 => Ok(()),
+			1 => plumb_location(location_to_plumb(&locs[0])),
+			_ => {
+				self.locations_output(locs);
+				Ok(())
+			}
+		}
+	}
+	/// Sets the output to the sorted locations, each followed by the line it starts on.
+	fn locations_output(&mut self, mut locs: Vec<Location>) {
+		locs.sort_by(cmp_location);
+		locs.dedup();
+		let mut o = Vec::new();
+		let mut files: HashMap<Url, String> = HashMap::new();
+		for x in locs {
+			o.push(location_to_plumb(&x));
+			let text =
+				files
+					.entry(x.uri.clone())
+					.or_insert_with(|| match self.get_sw_by_url(&x.uri) {
+						Some((_, win)) => win.text().unwrap_or((0, "".into())).1,
+						None => read_to_string(x.uri.path()).unwrap_or("".into()),
+					});
+			if let Some(line) = text.lines().nth(x.range.start.line.try_into().unwrap()) {
+				o.push(format!("\t{}", line.trim()));
+			}
+		}
+		if o.len() > 0 {
+			self.output = o.join("\n");
+		}
+	}
+	/// Sets the output to the workspace symbols, grouped by file and kind. Symbols without a
+	/// range are resolved when clicked.
+	fn workspace_symbols_output(&mut self) {
+		let resolve = match self.capabilities.get(&self.symbol_client) {
+			Some(ServerCapabilities {
+				workspace_symbol_provider: Some(OneOf::Right(opts)),
+				..
+			}) => opts.resolve_provider.unwrap_or(false),
+			_ => false,
+		};
+		let mut lines = vec![];
+		let mut symbols: Vec<(usize, &WorkspaceSymbol)> =
+			self.workspace_symbols.iter().enumerate().collect();
+		let uri = |ws: &WorkspaceSymbol| match &ws.location {
+			OneOf::Left(loc) => loc.uri.clone(),
+			OneOf::Right(loc) => loc.uri.clone(),
+		};
+		let line = |ws: &WorkspaceSymbol| match &ws.location {
+			OneOf::Left(loc) => loc.range.start.line,
+			OneOf::Right(_) => 0,
+		};
+		symbols.sort_by(|(_, a), (_, b)| {
+			uri(a)
+				.path()
+				.cmp(uri(b).path())
+				.then(format!("{:?}", a.kind).cmp(&format!("{:?}", b.kind)))
+				.then(line(a).cmp(&line(b)))
+		});
+		let mut o: Vec<String> = vec![];
+		let mut last_path = "".to_string();
+		for (idx, ws) in symbols {
+			let uri = uri(ws);
+			if uri.path() != last_path {
+				last_path = uri.path().to_string();
+				o.push(last_path.clone());
+			}
+			let container = match &ws.container_name {
+				Some(c) if !c.is_empty() => format!("{}::", c),
+				_ => "".to_string(),
+			};
+			// Unresolved symbols only know their file.
+			let loc = match &ws.location {
+				OneOf::Left(loc) => location_to_plumb(loc),
+				OneOf::Right(loc) => {
+					if resolve {
+						lines.push((o.len(), idx));
+					}
+					loc.uri.path().to_string()
+				}
+			};
+			o.push(format!(
+				"\t{}{} ({:?}): {}",
+				container, ws.name, ws.kind, loc
+			));
+		}
+		self.output = if o.is_empty() {
+			"no symbols found".to_string()
+		} else {
+			o.join("\n")
+		};
+		self.symbol_lines = (self.output.clone(), lines);
+	}
+	/// Resolves the location of a workspace symbol, going to it once known.
+	fn resolve_symbol(&mut self, idx: usize) -> Result<()> {
+		let ws = self.workspace_symbols[idx].clone();
+		let url = match &ws.location {
+			OneOf::Left(loc) => return plumb_location(location_to_plumb(loc)),
+			OneOf::Right(loc) => loc.uri.clone(),
+		};
+		let client_name = self.symbol_client.clone();
+		let msg_id = self.send_request::<WorkspaceSymbolResolve>(&client_name, url, ws)?;
+		self.symbol_resolves
+			.insert(ClientId::new(&client_name, msg_id), idx);
+		Ok(())
+	}
+	/// Returns the selected text of the acre window.
+	fn selection(&mut self) -> Result<String> {
+		self.w.ctl("addr=dot")?;
+		let (q0, q1) = self.w.read_addr()?;
+		let mut body = String::new();
+		self.w.read(File::Body)?.read_to_string(&mut body)?;
+		Ok(body
+			.chars()
+			.skip(q0 as usize)
+			.take((q1 - q0) as usize)
+			.collect())
+	}
+	/// Asks for completions again after the document changed if the server said its last list
+	/// was incomplete.
+	fn requery_completion(&mut self, url: &Url) -> Result<()> {
+		let client_name = match &self.current_hover {
+			Some(hover) if &hover.url == url && hover.completion_incomplete => {
+				hover.client_name.clone()
+			}
+			_ => return Ok(()),
+		};
+		let text_document_position = match self.get_sw_by_url(url) {
+			Some((_, sw)) => sw.text_doc_pos()?,
+			None => return Ok(()),
+		};
+		self.send_request::<Completion>(
+			&client_name,
+			url.clone(),
+			CompletionParams {
+				text_document_position,
+				work_done_progress_params,
+				partial_result_params,
+				context: Some(CompletionContext {
+					trigger_kind: CompletionTriggerKind::TRIGGER_FOR_INCOMPLETE_COMPLETIONS,
+					trigger_character: None,
+				}),
+			},
+		)?;
+		Ok(())
+	}
 	fn cmd_put(&mut self, ev: LogEvent) -> Result<()> {
-		self.did_change(ev.name.clone(), ev.id)?;
+		if self.did_change(ev.name.clone(), ev.id)? {
+			if let Some(url) = self
+				.get_sw_by_name_id(&ev.name, &ev.id)
+				.map(|sw| sw.url.clone())
+			{
+				self.requery_completion(&url)?;
+			}
+		}
 		let sw = match self.get_sw_by_name_id(&ev.name, &ev.id) {
 			Some(sw) => sw,
 			None => return Ok(()),
@@ -1513,6 +4007,8 @@ impl Server {
 				text: None,
 			},
 		)?;
+		self.pull_diagnostics(client_name, url.clone())?;
+		self.pull_workspace_diagnostics(client_name)?;
 		let capabilities = self.capabilities.get(client_name).unwrap();
 		if self
 			.config
@@ -1523,19 +4019,13 @@ impl Server {
 			.unwrap_or(true)
 			&& capabilities.document_formatting_provider.is_some()
 		{
+			let options = self.formatting_options(client_name, url.path());
 			self.send_request::<Formatting>(
 				client_name,
 				url,
 				DocumentFormattingParams {
 					text_document,
-					options: FormattingOptions {
-						tab_size: 4,
-						insert_spaces: false,
-						properties: HashMap::new(),
-						trim_trailing_whitespace: Some(true),
-						insert_final_newline: Some(true),
-						trim_final_newlines: Some(true),
-					},
+					options,
 					work_done_progress_params: WorkDoneProgressParams {
 						work_done_token: None,
 					},
@@ -1574,7 +4064,19 @@ impl Server {
 			for (_, c) in &self.clients {
 				sel.recv(&c.msg_r);
 			}
-			let index = sel.ready();
+			let index = match self.plumb_timeout() {
+				Some(timeout) => match sel.ready_timeout(timeout) {
+					Ok(index) => index,
+					Err(_) => {
+						self.expire_plumbed_edits();
+						if sync_s.is_empty() {
+							sync_s.send(())?;
+						}
+						continue;
+					}
+				},
+				None => sel.ready(),
+			};
 
 			match index {
 				_ if index == sel_log_r => {
@@ -1589,6 +4091,11 @@ impl Server {
 								no_sync = true;
 							}
 							"new" | "del" => {
+								if ev.op == "del"
+									&& self.diag_w.as_ref().map(|w| w.id()) == Some(ev.id)
+								{
+									self.diag_w = None;
+								}
 								self.sync_windows()?;
 							}
 							_ => {
@@ -1652,20 +4159,267 @@ impl Drop for Server {
 	}
 }
 
-fn goto_definition(goto: &GotoDefinitionResponse) -> Result<()> {
-	let loc: &Location = match goto {
-		GotoDefinitionResponse::Array(locs) => match locs.len() {
-			0 => return Ok(()),
-			_ => &locs[0],
+/// Runs a cargo runnable in a new window named after its directory, streaming its output
+/// into the window as it is produced.
+fn run_runnable(runnable: &lsp::Runnable) -> Result<()> {
+	if runnable.kind != "cargo" {
+		bail!("unsupported runnable kind: {}", runnable.kind);
+	}
+	let args = &runnable.args;
+	let dir = match args.cwd.as_ref().or(args.workspace_root.as_ref()) {
+		Some(dir) => dir.clone(),
+		None => match &runnable.location {
+			Some(loc) => std::path::Path::new(loc.target_uri.path())
+				.parent()
+				.map_or("/".into(), |p| p.to_string_lossy().to_string()),
+			None => bail!("runnable has no directory"),
 		},
-		GotoDefinitionResponse::Scalar(loc) => &loc,
-		_ => panic!("unknown definition response: {:?}", goto),
 	};
-	let plumb = location_to_plumb(loc);
-	plumb_location(plumb)?;
+	let mut cmd_args = args.cargo_args.clone();
+	cmd_args.extend(args.cargo_extra_args.iter().cloned());
+	if !args.executable_args.is_empty() {
+		cmd_args.push("--".into());
+		cmd_args.extend(args.executable_args.iter().cloned());
+	}
+	let program = args.override_cargo.clone().unwrap_or("cargo".into());
+	let mut w = Win::new()?;
+	w.name(&format!("{}/+runnable", dir.trim_end_matches('/')))?;
+	w.write(File::Body, &format!("{} {}\n", program, cmd_args.join(" ")))?;
+	let spawned = std::process::Command::new(&program)
+		.args(&cmd_args)
+		.current_dir(&dir)
+		.envs(&args.environment)
+		.stdin(std::process::Stdio::null())
+		.stdout(std::process::Stdio::piped())
+		.stderr(std::process::Stdio::piped())
+		.spawn();
+	let mut child = match spawned {
+		Ok(child) => child,
+		Err(err) => {
+			w.write(File::Body, &format!("{}\n", err))?;
+			return w.ctl("clean");
+		}
+	};
+	let (line_s, line_r) = crossbeam_channel::unbounded::<String>();
+	fn forward<R: Read + Send + 'static>(r: R, s: crossbeam_channel::Sender<String>) {
+		thread::spawn(move || {
+			let mut r = std::io::BufReader::new(r);
+			loop {
+				let mut line = String::new();
+				match std::io::BufRead::read_line(&mut r, &mut line) {
+					Ok(0) | Err(_) => return,
+					Ok(_) => {
+						if s.send(line).is_err() {
+							return;
+						}
+					}
+				}
+			}
+		});
+	}
+	forward(child.stdout.take().unwrap(), line_s.clone());
+	forward(child.stderr.take().unwrap(), line_s);
+	thread::spawn(move || {
+		// The channel closes once both stdout and stderr are done.
+		let streamed = line_r
+			.iter()
+			.try_for_each(|line| w.write(File::Body, &line));
+		if let Err(err) = &streamed {
+			// Nothing is left to show the output, so stop the process.
+			eprintln!("runnable error: {}", err);
+			let _ = child.kill();
+		}
+		let status = child.wait();
+		if streamed.is_err() {
+			return;
+		}
+		let done = match status {
+			Ok(status) => w.write(File::Body, &format!("\n{}\n", status)),
+			Err(err) => w.write(File::Body, &format!("\n{}\n", err)),
+		};
+		if let Err(err) = done.and_then(|_| w.ctl("clean")) {
+			eprintln!("runnable error: {}", err);
+		}
+	});
+	Ok(())
+}
+
+/// Returns the lines that have inlay hints, prefixed by their line number and with the hints
+/// inserted into the text where an editor would draw them.
+fn inlay_hint_lines(
+	first_line: u32,
+	lines: &[String],
+	hints: &[InlayHint],
+	encoding: Encoding,
+) -> Vec<String> {
+	let mut o = vec![];
+	for (i, text) in lines.iter().enumerate() {
+		let line = first_line + i as u32;
+		let mut hints: Vec<&InlayHint> = hints.iter().filter(|h| h.position.line == line).collect();
+		if hints.is_empty() {
+			continue;
+		}
+		let nl = match NlOffsets::with_encoding(text.as_bytes(), encoding) {
+			Ok(nl) => nl,
+			Err(_) => continue,
+		};
+		hints.sort_by_key(|h| h.position.character);
+		let chars: Vec<char> = text.chars().collect();
+		let mut s = String::new();
+		let mut pos = 0;
+		for hint in hints {
+			let offset = (nl.line_to_offset(0, hint.position.character) as usize).min(chars.len());
+			s.extend(&chars[pos..offset.max(pos)]);
+			pos = offset.max(pos);
+			if hint.padding_left.unwrap_or(false) {
+				s.push(' ');
+			}
+			match &hint.label {
+				InlayHintLabel::String(label) => s.push_str(label),
+				InlayHintLabel::LabelParts(parts) => {
+					for part in parts {
+						s.push_str(&part.value);
+					}
+				}
+			}
+			if hint.padding_right.unwrap_or(false) {
+				s.push(' ');
+			}
+		}
+		s.extend(&chars[pos..]);
+		o.push(format!("{}: {}", line + 1, s.trim()));
+	}
+	o
+}
+
+/// Returns the part of a server's options requested by a workspace/configuration item. The
+/// section is looked up as a dotted path in the options. Since the options are usually
+/// written for the server itself, asking for the section named after the server returns them
+/// all.
+fn config_section(client_name: &str, options: &Value, section: Option<&str>) -> Value {
+	let section = match section {
+		Some(s) if !s.is_empty() => s,
+		_ => return options.clone(),
+	};
+	let mut v = options;
+	for part in section.split('.') {
+		match v.get(part) {
+			Some(part) => v = part,
+			None if section == client_name => return options.clone(),
+			None => return Value::Null,
+		}
+	}
+	v.clone()
+}
+
+/// Applies edits to a file that has no acme window by rewriting it on disk.
+/// Returns the files whose text edit changes.
+fn edit_urls(edit: &WorkspaceEdit) -> HashSet<Url> {
+	let mut urls: HashSet<Url> = edit
+		.changes
+		.iter()
+		.flatten()
+		.map(|(url, _)| url.clone())
+		.collect();
+	match &edit.document_changes {
+		Some(DocumentChanges::Edits(edits)) => {
+			urls.extend(edits.iter().map(|e| e.text_document.uri.clone()));
+		}
+		Some(DocumentChanges::Operations(ops)) => {
+			urls.extend(ops.iter().filter_map(|op| match op {
+				DocumentChangeOperation::Edit(e) => Some(e.text_document.uri.clone()),
+				DocumentChangeOperation::Op(_) => None,
+			}));
+		}
+		None => {}
+	}
+	urls
+}
+
+fn apply_file_edits(path: &str, edits: &[TextEdit], encoding: Encoding) -> Result<()> {
+	let body = read_to_string(path)?;
+	let offsets = NlOffsets::with_encoding(body.as_bytes(), encoding)?;
+	let mut edits = edits.to_vec();
+	edits.sort_by(|a, b| cmp_range(&a.range, &b.range));
+	let mut chars: Vec<char> = body.chars().collect();
+	for edit in edits.iter().rev() {
+		let soff = offsets.line_to_offset(edit.range.start.line, edit.range.start.character);
+		let eoff = offsets.line_to_offset(edit.range.end.line, edit.range.end.character);
+		chars.splice(soff as usize..eoff as usize, edit.new_text.chars());
+	}
+	std::fs::write(path, chars.into_iter().collect::<String>())?;
 	Ok(())
 }
 
+/// Returns the pull diagnostics options of a server, if it supports them.
+fn diagnostic_options(caps: &ServerCapabilities) -> Option<&DiagnosticOptions> {
+	match caps.diagnostic_provider.as_ref()? {
+		DiagnosticServerCapabilities::Options(opts) => Some(opts),
+		DiagnosticServerCapabilities::RegistrationOptions(opts) => Some(&opts.diagnostic_options),
+	}
+}
+
+/// Returns how the server wants to be sent document changes.
+fn sync_kind(caps: &ServerCapabilities) -> TextDocumentSyncKind {
+	match &caps.text_document_sync {
+		Some(TextDocumentSyncCapability::Kind(kind)) => *kind,
+		Some(TextDocumentSyncCapability::Options(opts)) => {
+			opts.change.unwrap_or(TextDocumentSyncKind::NONE)
+		}
+		// Servers that don't say have always been sent the full text.
+		None => TextDocumentSyncKind::FULL,
+	}
+}
+
+/// Returns ranged change events that turn old into new. The ranges cover whole lines, and the
+/// events are ordered from the end of the document so each one's range is still valid after
+/// the previous ones are applied.
+fn text_changes(old: &str, new: &str, encoding: Encoding) -> Vec<TextDocumentContentChangeEvent> {
+	let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+	let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+	// Hunks of (first old line, end old line, replacement text).
+	let mut hunks: Vec<(usize, usize, String)> = vec![];
+	let mut hunk: Option<(usize, usize, String)> = None;
+	let mut line = 0;
+	for d in diff::slice(&old_lines, &new_lines) {
+		match d {
+			diff::Result::Both(_, _) => {
+				hunks.extend(hunk.take());
+				line += 1;
+			}
+			diff::Result::Left(_) => {
+				hunk.get_or_insert((line, line, String::new())).1 += 1;
+				line += 1;
+			}
+			diff::Result::Right(s) => {
+				hunk.get_or_insert((line, line, String::new()))
+					.2
+					.push_str(s);
+			}
+		}
+	}
+	hunks.extend(hunk.take());
+	hunks
+		.into_iter()
+		.rev()
+		.map(|(start, end, text)| {
+			// The last line has no newline to end the range after, so end at its last
+			// character instead.
+			let end = if end == old_lines.len() && end > 0 && !old.ends_with('\n') {
+				let last = old_lines[end - 1];
+				Position::new(end as u32 - 1, encoding.count(last))
+			} else {
+				Position::new(end as u32, 0)
+			};
+			TextDocumentContentChangeEvent {
+				range: Some(Range::new(Position::new(start as u32, 0), end)),
+				range_length: None,
+				text,
+			}
+		})
+		.collect()
+}
+
 fn location_to_plumb(l: &Location) -> String {
 	// Including the character here apparently isn't useful because the right click
 	// event from acme doesn't include it, only the line. Why is this?
@@ -1708,6 +4462,12 @@ fn cmp_range(a: &Range, b: &Range) -> Ordering {
 	return cmp_position(&a.end, &b.end);
 }
 
+/// Reports whether a contains b.
+fn range_contains(a: &Range, b: &Range) -> bool {
+	cmp_position(&a.start, &b.start) != Ordering::Greater
+		&& cmp_position(&a.end, &b.end) != Ordering::Less
+}
+
 fn cmp_position(a: &Position, b: &Position) -> Ordering {
 	if a.line != b.line {
 		return a.line.cmp(&b.line);
@@ -1722,6 +4482,9 @@ fn extract_doc(d: &Documentation) -> &str {
 	}
 }
 
+/// How long to wait for a plumbed file's window to open before writing its edits to disk.
+const PLUMB_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[allow(non_upper_case_globals)]
 const work_done_progress_params: WorkDoneProgressParams = WorkDoneProgressParams {
 	work_done_token: None,
@@ -1730,3 +4493,74 @@ const work_done_progress_params: WorkDoneProgressParams = WorkDoneProgressParams
 const partial_result_params: PartialResultParams = PartialResultParams {
 	partial_result_token: None,
 };
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+		TextDocumentContentChangeEvent {
+			range: Some(Range::new(
+				Position::new(start.0, start.1),
+				Position::new(end.0, end.1),
+			)),
+			range_length: None,
+			text: text.to_string(),
+		}
+	}
+
+	#[test]
+	fn text_changes_edges() {
+		let utf16 = Encoding::Utf16;
+		assert_eq!(text_changes("a\nb\n", "a\nb\n", utf16), vec![]);
+		// Empty old or new text.
+		assert_eq!(
+			text_changes("", "a\n", utf16),
+			vec![change((0, 0), (0, 0), "a\n")]
+		);
+		assert_eq!(
+			text_changes("a\n", "", utf16),
+			vec![change((0, 0), (1, 0), "")]
+		);
+		// Appended, inserted and deleted lines.
+		assert_eq!(
+			text_changes("a\n", "a\nb\n", utf16),
+			vec![change((1, 0), (1, 0), "b\n")]
+		);
+		assert_eq!(
+			text_changes("a\nc\n", "a\nb\nc\n", utf16),
+			vec![change((1, 0), (1, 0), "b\n")]
+		);
+		assert_eq!(
+			text_changes("a\nb\nc\n", "a\nc\n", utf16),
+			vec![change((1, 0), (2, 0), "")]
+		);
+		// Without a trailing newline the last line ends at its last character.
+		assert_eq!(
+			text_changes("a\nb", "a\nc", utf16),
+			vec![change((1, 0), (1, 1), "c")]
+		);
+		assert_eq!(
+			text_changes("a", "a\nb", utf16),
+			vec![change((0, 0), (0, 1), "a\nb")]
+		);
+		assert_eq!(
+			text_changes("a", "a\n", utf16),
+			vec![change((0, 0), (0, 1), "a\n")]
+		);
+		// Multiple hunks come last first so earlier ranges stay valid.
+		assert_eq!(
+			text_changes("a\nb\nc\nd\n", "a\nB\nc\nD\n", utf16),
+			vec![change((3, 0), (4, 0), "D\n"), change((1, 0), (2, 0), "B\n")]
+		);
+		// The last line's end is counted in the encoding.
+		assert_eq!(
+			text_changes("é𝄞", "x", utf16),
+			vec![change((0, 0), (0, 3), "x")]
+		);
+		assert_eq!(
+			text_changes("é𝄞", "x", Encoding::Utf8),
+			vec![change((0, 0), (0, 6), "x")]
+		);
+	}
+}
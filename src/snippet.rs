@@ -0,0 +1,276 @@
+/// A snippet with its syntax removed.
+#[derive(Debug, PartialEq)]
+pub struct Expansion {
+	pub text: String,
+	/// Rune ranges in text of each tabstop, in the order they are visited: $1, $2, ..., then $0.
+	/// Only the first occurrence of a tabstop that appears more than once is kept. If the snippet
+	/// has tabstops but no $0, the end of the text is added as the final tabstop.
+	pub tabstops: Vec<(usize, usize)>,
+}
+
+/// Expands an LSP snippet. Placeholders and variables insert their default text (or nothing),
+/// choices insert their first option, and transforms are dropped.
+pub fn expand(snippet: &str) -> Expansion {
+	let chars: Vec<char> = snippet.chars().collect();
+	let mut p = Parser {
+		chars: &chars,
+		pos: 0,
+		text: vec![],
+		stops: vec![],
+	};
+	p.parse(&[]);
+	let mut stops = p.stops;
+	// Stable sort keeps the first occurrence of each number first.
+	stops.sort_by_key(|(n, _, _)| if *n == 0 { u32::MAX } else { *n });
+	stops.dedup_by_key(|(n, _, _)| *n);
+	let len = p.text.len();
+	if !stops.is_empty() && stops.last().map(|(n, _, _)| *n) != Some(0) {
+		stops.push((0, len, len));
+	}
+	Expansion {
+		text: p.text.into_iter().collect(),
+		tabstops: stops.into_iter().map(|(_, s, e)| (s, e)).collect(),
+	}
+}
+
+struct Parser<'a> {
+	chars: &'a [char],
+	pos: usize,
+	text: Vec<char>,
+	/// (number, start, end) of each tabstop.
+	stops: Vec<(u32, usize, usize)>,
+}
+
+impl<'a> Parser<'a> {
+	fn peek(&self) -> Option<char> {
+		self.chars.get(self.pos).copied()
+	}
+	/// Parses text until one of the unescaped stop characters or the end.
+	fn parse(&mut self, stop: &[char]) {
+		while let Some(c) = self.peek() {
+			if stop.contains(&c) {
+				return;
+			}
+			match c {
+				'\\' => {
+					// Only syntax characters can be escaped; other backslashes are literal.
+					match self.chars.get(self.pos + 1) {
+						Some(&n) if n == '$' || n == '}' || n == '\\' || stop.contains(&n) => {
+							self.text.push(n);
+							self.pos += 2;
+						}
+						_ => {
+							self.text.push(c);
+							self.pos += 1;
+						}
+					}
+				}
+				'$' => {
+					if !self.dollar() {
+						self.text.push(c);
+						self.pos += 1;
+					}
+				}
+				_ => {
+					self.text.push(c);
+					self.pos += 1;
+				}
+			}
+		}
+	}
+	/// Parses a tabstop, placeholder, choice or variable at a $. Returns false, consuming
+	/// nothing, if it isn't one.
+	fn dollar(&mut self) -> bool {
+		let start = self.pos;
+		let stops_len = self.stops.len();
+		self.pos += 1;
+		let braced = self.peek() == Some('{');
+		if braced {
+			self.pos += 1;
+		}
+		if let Some(n) = self.number() {
+			if !braced {
+				self.stops.push((n, self.text.len(), self.text.len()));
+				return true;
+			}
+			let text_start = self.text.len();
+			match self.peek() {
+				Some('}') => {
+					self.pos += 1;
+				}
+				Some(':') => {
+					self.pos += 1;
+					self.parse(&['}']);
+					if self.peek() != Some('}') {
+						return self.reset(start, text_start, stops_len);
+					}
+					self.pos += 1;
+				}
+				Some('|') => {
+					self.pos += 1;
+					let mut options = vec![];
+					loop {
+						let option_start = self.text.len();
+						self.parse(&[',', '|']);
+						options.push(self.text.split_off(option_start));
+						match self.peek() {
+							Some(',') => self.pos += 1,
+							Some('|') if self.chars.get(self.pos + 1) == Some(&'}') => {
+								self.pos += 2;
+								break;
+							}
+							_ => return self.reset(start, text_start, stops_len),
+						}
+					}
+					self.text
+						.extend(options.into_iter().next().unwrap_or_default());
+				}
+				_ => return self.reset(start, text_start, stops_len),
+			}
+			self.stops.push((n, text_start, self.text.len()));
+			return true;
+		}
+		let text_start = self.text.len();
+		if self.variable().is_none() {
+			return self.reset(start, text_start, stops_len);
+		}
+		if !braced {
+			return true;
+		}
+		match self.peek() {
+			Some('}') => {
+				self.pos += 1;
+			}
+			Some(':') => {
+				self.pos += 1;
+				self.parse(&['}']);
+				if self.peek() != Some('}') {
+					return self.reset(start, text_start, stops_len);
+				}
+				self.pos += 1;
+			}
+			Some('/') => {
+				// A transform: /regex/format/options}. Skip it, honouring escapes.
+				let mut slashes = 0;
+				while slashes < 3 {
+					match self.peek() {
+						Some('\\') => self.pos += 2,
+						Some('/') => {
+							slashes += 1;
+							self.pos += 1;
+						}
+						Some(_) => self.pos += 1,
+						None => return self.reset(start, text_start, stops_len),
+					}
+				}
+				while let Some(c) = self.peek() {
+					self.pos += 1;
+					if c == '}' {
+						return true;
+					}
+				}
+				return self.reset(start, text_start, stops_len);
+			}
+			_ => return self.reset(start, text_start, stops_len),
+		}
+		true
+	}
+	/// Undoes a failed parse, returning false.
+	fn reset(&mut self, pos: usize, text_len: usize, stops_len: usize) -> bool {
+		self.pos = pos;
+		self.text.truncate(text_len);
+		self.stops.truncate(stops_len);
+		false
+	}
+	fn number(&mut self) -> Option<u32> {
+		let start = self.pos;
+		while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+			self.pos += 1;
+		}
+		if start == self.pos {
+			return None;
+		}
+		self.chars[start..self.pos]
+			.iter()
+			.collect::<String>()
+			.parse()
+			.ok()
+	}
+	fn variable(&mut self) -> Option<String> {
+		let start = self.pos;
+		match self.peek() {
+			Some(c) if c == '_' || c.is_ascii_alphabetic() => {}
+			_ => return None,
+		}
+		while self
+			.peek()
+			.is_some_and(|c| c == '_' || c.is_ascii_alphanumeric())
+		{
+			self.pos += 1;
+		}
+		Some(self.chars[start..self.pos].iter().collect())
+	}
+}
+
+/// Escapes text so that it expands to itself.
+pub fn escape(text: &str) -> String {
+	let mut s = String::with_capacity(text.len());
+	for c in text.chars() {
+		if c == '$' || c == '}' || c == '\\' {
+			s.push('\\');
+		}
+		s.push(c);
+	}
+	s
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn check(snippet: &str, text: &str, tabstops: Vec<(usize, usize)>) {
+		assert_eq!(
+			expand(snippet),
+			Expansion {
+				text: text.to_string(),
+				tabstops,
+			},
+			"{}",
+			snippet
+		);
+	}
+
+	#[test]
+	fn expand_snippets() {
+		check("plain", "plain", vec![]);
+		check("foo($1)$0", "foo()", vec![(4, 4), (5, 5)]);
+		check(
+			"foo(${1:a}, ${2:b})",
+			"foo(a, b)",
+			vec![(4, 5), (7, 8), (9, 9)],
+		);
+		check("${2:x} ${1:y}", "x y", vec![(2, 3), (0, 1), (3, 3)]);
+		check(
+			"${1:outer ${2:inner}}",
+			"outer inner",
+			vec![(0, 11), (6, 11), (11, 11)],
+		);
+		check("${1|one,two|}", "one", vec![(0, 3), (3, 3)]);
+		check("${1|a\\,b,c|}", "a,b", vec![(0, 3), (3, 3)]);
+		check("\\$1 \\} \\\\", "$1 } \\", vec![]);
+		check("${TM_FILENAME:file}.rs", "file.rs", vec![]);
+		check("$TM_SELECTED_TEXT!", "!", vec![]);
+		check("${TM_FILENAME/(.*)/$1/g}x", "x", vec![]);
+		check("${1} and $1", " and ", vec![(0, 0), (5, 5)]);
+		check("cost $ 5", "cost $ 5", vec![]);
+		check("${1:unclosed", "${1:unclosed", vec![]);
+		check("$1${2:unclosed", "${2:unclosed", vec![(0, 0), (12, 12)]);
+		check("é${1:ü}", "éü", vec![(1, 2), (2, 2)]);
+	}
+
+	#[test]
+	fn escape_text() {
+		let text = "use a::{b, c}; let $x = \\$1;";
+		check(&escape(text), text, vec![]);
+	}
+}